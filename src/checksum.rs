@@ -2,30 +2,314 @@ use std::ffi::OsStr;
 /// Checksum calculation module
 ///
 /// Contains all functions for various checksum calculation.
+use std::fmt::Display;
 use std::fs::File;
-use std::io::Result as IoResult;
-use std::io::{prelude::Read, BufReader};
+use std::hash::{Hash, Hasher};
+use std::io::{self, prelude::Read, Result as IoResult, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::str::FromStr;
 
-use blake2::{Blake2b512, Digest};
+use blake2::Blake2b512;
+use md5::Md5;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::ConfirmerError;
+
+/// Size of the buffer for reading files
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Hash algorithm used to compare files
+///
+/// Serialized as its [name](HashAlgorithm::name).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum HashAlgorithm {
+    /// BLAKE2b with 512 bit output
+    #[default]
+    Blake2b,
+    /// BLAKE3 with 256 bit output
+    Blake3,
+    /// SHA-256
+    Sha256,
+    /// 128 bit variant of xxHash3, fast but not cryptographic
+    Xxh3,
+    /// CRC-32C, fast but with a high chance of collisions on large directories
+    Crc32c,
+    /// MD5, only for compatibility with existing checksum files
+    Md5,
+    /// Algorithm implemented outside of the crate
+    ///
+    /// Manifests with its checksums can be compared and saved, but not loaded, as its name is not
+    /// known to [from_str](HashAlgorithm::from_str).
+    Custom(CustomAlgorithm),
+}
+
+/// Hash algorithm implemented outside of the crate, see [HashAlgorithm::Custom]
+///
+/// Algorithms are told apart by their names.
+#[derive(Clone, Copy, Debug)]
+pub struct CustomAlgorithm {
+    /// Name of the algorithm, stored in manifests and in the [HashCache](crate::HashCache)
+    pub name: &'static str,
+    /// Create new hasher of the algorithm
+    pub new_hasher: fn() -> Box<dyn FileHasher>,
+}
+
+impl PartialEq for CustomAlgorithm {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for CustomAlgorithm {}
+
+impl Hash for CustomAlgorithm {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+    }
+}
+
+impl HashAlgorithm {
+    /// All available algorithms
+    pub const ALL: [HashAlgorithm; 6] = [
+        HashAlgorithm::Blake2b,
+        HashAlgorithm::Blake3,
+        HashAlgorithm::Sha256,
+        HashAlgorithm::Xxh3,
+        HashAlgorithm::Crc32c,
+        HashAlgorithm::Md5,
+    ];
+
+    /// Name of the algorithm as accepted by [from_str](HashAlgorithm::from_str)
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Blake2b => "blake2b",
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Xxh3 => "xxh3",
+            HashAlgorithm::Crc32c => "crc32c",
+            HashAlgorithm::Md5 => "md5",
+            HashAlgorithm::Custom(custom) => custom.name,
+        }
+    }
+
+    /// Create new hasher for the algorithm
+    pub(crate) fn hasher(&self) -> Box<dyn FileHasher> {
+        match self {
+            HashAlgorithm::Blake2b => Box::new(DigestHasher(Blake2b512::new())),
+            HashAlgorithm::Blake3 => Box::new(blake3::Hasher::new()),
+            HashAlgorithm::Sha256 => Box::new(DigestHasher(Sha256::new())),
+            HashAlgorithm::Xxh3 => Box::new(Xxh3::new()),
+            HashAlgorithm::Crc32c => Box::new(Crc32cHasher(0)),
+            HashAlgorithm::Md5 => Box::new(DigestHasher(Md5::new())),
+            HashAlgorithm::Custom(custom) => (custom.new_hasher)(),
+        }
+    }
+
+    /// Guess the algorithm from name of a checksum file, e.g. `SHA256SUMS` or `data.b2sum`
+    pub fn from_checksum_file_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        if name.contains("sha256") {
+            Some(HashAlgorithm::Sha256)
+        } else if name.contains("b2sum") || name.contains("blake2") {
+            Some(HashAlgorithm::Blake2b)
+        } else if name.contains("b3sum") || name.contains("blake3") {
+            Some(HashAlgorithm::Blake3)
+        } else if name.contains("md5") {
+            Some(HashAlgorithm::Md5)
+        } else {
+            None
+        }
+    }
+}
+
+impl Display for HashAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        HashAlgorithm::ALL.into_iter().find(|alg| alg.name() == s).ok_or_else(|| {
+            let names: Vec<&str> = HashAlgorithm::ALL.iter().map(|alg| alg.name()).collect();
+            format!("Unknown hash algorithm {s:?}, expected one of: {}", names.join(", "))
+        })
+    }
+}
+
+impl Serialize for HashAlgorithm {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for HashAlgorithm {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Incremental checksum calculation of file contents
+///
+/// Implement it to compare files with an algorithm the crate does not provide, see
+/// [HashAlgorithm::Custom].
+pub trait FileHasher {
+    /// Feed next chunk of data to the hasher
+    fn update(&mut self, data: &[u8]);
+
+    /// Get the checksum as a lowercase hex string
+    fn finalize(self: Box<Self>) -> String;
+}
+
+/// Hasher for algorithms implementing [Digest]
+struct DigestHasher<D>(D);
+
+impl<D: Digest> FileHasher for DigestHasher<D>
+where
+    sha2::digest::Output<D>: std::fmt::LowerHex,
+{
+    fn update(&mut self, data: &[u8]) {
+        Digest::update(&mut self.0, data);
+    }
+
+    fn finalize(self: Box<Self>) -> String {
+        format!("{:x}", self.0.finalize())
+    }
+}
+
+impl FileHasher for blake3::Hasher {
+    fn update(&mut self, data: &[u8]) {
+        blake3::Hasher::update(self, data);
+    }
+
+    fn finalize(self: Box<Self>) -> String {
+        blake3::Hasher::finalize(&self).to_hex().to_string()
+    }
+}
+
+impl FileHasher for Xxh3 {
+    fn update(&mut self, data: &[u8]) {
+        Xxh3::update(self, data);
+    }
+
+    fn finalize(self: Box<Self>) -> String {
+        format!("{:032x}", self.digest128())
+    }
+}
+
+/// Hasher for CRC-32C, holds the checksum of data so far
+struct Crc32cHasher(u32);
+
+impl FileHasher for Crc32cHasher {
+    fn update(&mut self, data: &[u8]) {
+        self.0 = crc32c::crc32c_append(self.0, data);
+    }
+
+    fn finalize(self: Box<Self>) -> String {
+        format!("{:08x}", self.0)
+    }
+}
 
 /// Calculate checksum for a whole file
 ///
 /// # Arguments
 /// * `path` - path to the file to be checksummed
-pub(crate) fn get_blake2_checksum(path: &OsStr) -> IoResult<String> {
-    let mut hasher = Blake2b512::new();
-    let mut buffer = [0u8; 1024];
+/// * `algorithm` - hash algorithm to use
+/// * `on_read` - called with the number of bytes after each read
+pub(crate) fn get_checksum(
+    path: &OsStr,
+    algorithm: HashAlgorithm,
+    on_read: &dyn Fn(u64),
+) -> Result<String, ConfirmerError> {
+    let mut hasher = algorithm.hasher();
+    let file = open_file(path)?;
+
+    update_from_reader(hasher.as_mut(), file, on_read).map_err(|e| read_error(path, e))?;
+
+    Ok(hasher.finalize())
+}
+
+/// Calculate checksum of a symbolic link from its target path
+///
+/// The target is prefixed, so that the link is never found as a copy of a regular file containing
+/// the target path.
+///
+/// # Arguments
+/// * `target` - raw bytes of the target path
+/// * `algorithm` - hash algorithm to use
+pub(crate) fn get_symlink_checksum(target: &[u8], algorithm: HashAlgorithm) -> String {
+    let mut hasher = algorithm.hasher();
+    hasher.update(b"copcon-symlink\0");
+    hasher.update(target);
+    hasher.finalize()
+}
+
+/// Calculate checksum of the first and the last `block_size` bytes of a file
+///
+/// The checksum is cheap to calculate even for large files and is used to rule out files of the
+/// same size before calculating checksum of the whole file. For files shorter than two blocks,
+/// the blocks overlap.
+///
+/// # Arguments
+/// * `path` - path to the file to be checksummed
+/// * `block_size` - number of bytes checksummed at each end of the file
+/// * `algorithm` - hash algorithm to use
+/// * `on_read` - called with the number of bytes after each read
+pub(crate) fn get_partial_checksum(
+    path: &OsStr,
+    block_size: u64,
+    algorithm: HashAlgorithm,
+    on_read: &dyn Fn(u64),
+) -> Result<String, ConfirmerError> {
+    let mut hasher = algorithm.hasher();
+    let mut file = open_file(path)?;
+
+    let mut read_ends = || -> IoResult<()> {
+        let file_size = file.metadata()?.len();
+        update_from_reader(hasher.as_mut(), (&mut file).take(block_size), on_read)?;
+        file.seek(SeekFrom::Start(file_size.saturating_sub(block_size)))?;
+        update_from_reader(hasher.as_mut(), (&mut file).take(block_size), on_read)
+    };
+    read_ends().map_err(|e| read_error(path, e))?;
+
+    Ok(hasher.finalize())
+}
 
-    let mut buf_reader = BufReader::new(File::open(path)?);
+/// Open file for hashing
+fn open_file(path: &OsStr) -> Result<File, ConfirmerError> {
+    File::open(path).map_err(|source| ConfirmerError::Open { path: PathBuf::from(path), source })
+}
+
+/// Error for a file that could not be read
+fn read_error(path: &OsStr, source: io::Error) -> ConfirmerError {
+    ConfirmerError::Read { path: PathBuf::from(path), source }
+}
+
+/// Feed all data from reader to the hasher
+///
+/// # Arguments
+/// * `hasher` - hasher to be updated
+/// * `reader` - source of the data
+/// * `on_read` - called with the number of bytes after each read
+fn update_from_reader<R: Read>(
+    hasher: &mut dyn FileHasher,
+    mut reader: R,
+    on_read: &dyn Fn(u64),
+) -> IoResult<()> {
+    let mut buffer = vec![0u8; BUFFER_SIZE];
 
     loop {
-        let count = buf_reader.read(&mut buffer)?;
+        let count = reader.read(&mut buffer)?;
         if count == 0 {
             break;
         }
         hasher.update(&buffer[..count]);
+        on_read(count as u64);
     }
-
-    let result = format!("{:x}", hasher.finalize());
-    Ok(result)
+    Ok(())
 }
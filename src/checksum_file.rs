@@ -0,0 +1,182 @@
+//! Checksum files in the format of GNU coreutils
+//!
+//! Reads and writes files produced by `sha256sum`, `b2sum`, `md5sum` and similar tools, so that
+//! they can be used as [Manifest] without hashing the files again. Each line holds a checksum
+//! and a path relative to the directory of the checksum file:
+//! ``` text
+//! 6a4c...e1f0  path/to/file
+//! ```
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::paths::{path_from_bytes, path_to_bytes};
+use crate::{ConfirmerError, HashAlgorithm, Manifest, ManifestEntry};
+
+impl Manifest {
+    /// Load manifest from a checksum file in the format of `sha256sum`, `b2sum` or `md5sum`
+    ///
+    /// The paths in the file are relative to the directory of the checksum file. Checksum files
+    /// do not hold sizes of the files, so all files compared against them need to be hashed.
+    ///
+    /// # Arguments
+    /// * `path` - path to the checksum file
+    /// * `algorithm` - hash algorithm of the checksums, see
+    ///   [from_checksum_file_name](HashAlgorithm::from_checksum_file_name)
+    pub fn load_checksum_file<P: AsRef<Path>>(
+        path: P,
+        algorithm: HashAlgorithm,
+    ) -> Result<Self, ConfirmerError> {
+        let path = path.as_ref();
+        let contents = fs::read(path)
+            .map_err(|source| ConfirmerError::Storage { path: path.into(), source })?;
+        let hash_len = algorithm.hasher().finalize().len();
+
+        let mut entries = vec![];
+        for (line_number, line) in contents.split(|&b| b == b'\n').enumerate() {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            if line.is_empty() {
+                continue;
+            }
+            let (hash, name) = parse_line(line).ok_or_else(|| ConfirmerError::InvalidFile {
+                path: path.into(),
+                source: format!("Badly formatted line {}", line_number + 1).into(),
+            })?;
+            if hash.len() != hash_len {
+                return Err(ConfirmerError::InvalidFile {
+                    path: path.into(),
+                    source: format!("Line {} has no {} checksum", line_number + 1, algorithm)
+                        .into(),
+                });
+            }
+            entries.push(ManifestEntry {
+                path: path_from_bytes(name),
+                size: None,
+                mtime: None,
+                hash,
+            });
+        }
+
+        let root = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        Ok(Manifest { root, algorithm, entries })
+    }
+
+    /// Write checksums to a file in the format of `sha256sum`, `b2sum` or `md5sum`
+    ///
+    /// The paths are written relative to the root of the manifest, so the checksum file can be
+    /// verified by e.g. `sha256sum -c` run from the root directory.
+    ///
+    /// # Arguments
+    /// * `path` - path to the checksum file
+    pub fn save_checksum_file<P: AsRef<Path>>(&self, path: P) -> Result<(), ConfirmerError> {
+        let path = path.as_ref();
+        self._write_checksum_file(path)
+            .map_err(|source| ConfirmerError::Storage { path: path.into(), source })
+    }
+
+    /// Write checksums to a file, see [save_checksum_file](Manifest::save_checksum_file)
+    fn _write_checksum_file(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        for entry in &self.entries {
+            let name = path_to_bytes(&entry.path);
+            // Names with backslash or newline are escaped and marked by a leading backslash
+            if name.iter().any(|b| matches!(b, b'\\' | b'\n' | b'\r')) {
+                writer.write_all(b"\\")?;
+                writer.write_all(entry.hash.as_bytes())?;
+                writer.write_all(b"  ")?;
+                writer.write_all(&escape_name(&name))?;
+            } else {
+                writer.write_all(entry.hash.as_bytes())?;
+                writer.write_all(b"  ")?;
+                writer.write_all(&name)?;
+            }
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Split line of a checksum file into the checksum and the unescaped file name
+///
+/// Returns None if the line is not in the expected format
+fn parse_line(line: &[u8]) -> Option<(String, Vec<u8>)> {
+    let (escaped, line) = match line.strip_prefix(b"\\") {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let separator = line.iter().position(|&b| b == b' ')?;
+    let (hash, rest) = line.split_at(separator);
+    // The checksum is followed by a space and a mode flag (space for text, `*` for binary)
+    let name = match rest.get(1) {
+        Some(b' ') | Some(b'*') => &rest[2..],
+        _ => return None,
+    };
+    if hash.is_empty() || name.is_empty() || !hash.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    let hash = String::from_utf8(hash.to_ascii_lowercase()).ok()?;
+    let name = match escaped {
+        true => unescape_name(name)?,
+        false => name.to_vec(),
+    };
+    Some((hash, name))
+}
+
+/// Escape backslashes and newlines in a file name
+fn escape_name(name: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(name.len());
+    for &b in name {
+        match b {
+            b'\\' => escaped.extend_from_slice(b"\\\\"),
+            b'\n' => escaped.extend_from_slice(b"\\n"),
+            b'\r' => escaped.extend_from_slice(b"\\r"),
+            _ => escaped.push(b),
+        }
+    }
+    escaped
+}
+
+/// Reverse [escape_name], returns None on unknown escape sequence
+fn unescape_name(name: &[u8]) -> Option<Vec<u8>> {
+    let mut unescaped = Vec::with_capacity(name.len());
+    let mut bytes = name.iter();
+    while let Some(&b) = bytes.next() {
+        if b != b'\\' {
+            unescaped.push(b);
+            continue;
+        }
+        match bytes.next()? {
+            b'\\' => unescaped.push(b'\\'),
+            b'n' => unescaped.push(b'\n'),
+            b'r' => unescaped.push(b'\r'),
+            _ => return None,
+        }
+    }
+    Some(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(
+            parse_line(b"ABCD12  foo/bar.txt"),
+            Some(("abcd12".into(), b"foo/bar.txt".to_vec()))
+        );
+        assert_eq!(parse_line(b"abcd12 *bin"), Some(("abcd12".into(), b"bin".to_vec())));
+        assert_eq!(
+            parse_line(b"\\abcd12  a\\\\b\\nc"),
+            Some(("abcd12".into(), b"a\\b\nc".to_vec()))
+        );
+        assert_eq!(parse_line(b"abcd12 foo"), None);
+        assert_eq!(parse_line(b"xyz  foo"), None);
+        assert_eq!(escape_name(b"a\\b\nc"), b"a\\\\b\\nc".to_vec());
+    }
+}
@@ -1,20 +1,138 @@
 //! Module with CopyConfirmer-specific error
 
+use std::error::Error;
 use std::fmt::Display;
 use std::io;
+use std::path::{Path, PathBuf};
 
 /// An error produced when comparing directories
 #[derive(Debug)]
-pub struct ConfirmerError(pub String);
+pub enum ConfirmerError {
+    /// Directory could not be walked, e.g. it does not exist or cannot be listed
+    Walk {
+        /// Path that could not be accessed
+        path: PathBuf,
+        /// Underlying error
+        source: io::Error,
+    },
+    /// File could not be opened for hashing
+    Open {
+        /// Path of the file
+        path: PathBuf,
+        /// Underlying error
+        source: io::Error,
+    },
+    /// File could not be read while hashing
+    Read {
+        /// Path of the file
+        path: PathBuf,
+        /// Underlying error
+        source: io::Error,
+    },
+    /// A thread panicked while calculating hashes
+    ThreadPanic,
+    /// Manifest, checksum file or hash cache could not be read or written
+    Storage {
+        /// Path of the file
+        path: PathBuf,
+        /// Underlying error
+        source: io::Error,
+    },
+    /// Manifest, checksum file or hash cache has invalid contents
+    InvalidFile {
+        /// Path of the file
+        path: PathBuf,
+        /// Description of the problem
+        source: Box<dyn Error + Send + Sync>,
+    },
+    /// The comparison cannot be run with given settings
+    InvalidSettings(String),
+}
+
+impl ConfirmerError {
+    /// Path of the file or directory the error relates to
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            ConfirmerError::Walk { path, .. }
+            | ConfirmerError::Open { path, .. }
+            | ConfirmerError::Read { path, .. }
+            | ConfirmerError::Storage { path, .. }
+            | ConfirmerError::InvalidFile { path, .. } => Some(path),
+            ConfirmerError::ThreadPanic | ConfirmerError::InvalidSettings(_) => None,
+        }
+    }
+
+    /// Copy of the error for another path, e.g. a hardlink of the file
+    pub(crate) fn with_path(&self, path: PathBuf) -> Self {
+        let copy = |source: &io::Error| io::Error::new(source.kind(), source.to_string());
+        match self {
+            ConfirmerError::Walk { source, .. } => {
+                ConfirmerError::Walk { path, source: copy(source) }
+            }
+            ConfirmerError::Open { source, .. } => {
+                ConfirmerError::Open { path, source: copy(source) }
+            }
+            ConfirmerError::Read { source, .. } => {
+                ConfirmerError::Read { path, source: copy(source) }
+            }
+            ConfirmerError::Storage { source, .. } => {
+                ConfirmerError::Storage { path, source: copy(source) }
+            }
+            ConfirmerError::InvalidFile { source, .. } => {
+                ConfirmerError::InvalidFile { path, source: source.to_string().into() }
+            }
+            ConfirmerError::ThreadPanic => ConfirmerError::ThreadPanic,
+            ConfirmerError::InvalidSettings(message) => {
+                ConfirmerError::InvalidSettings(message.clone())
+            }
+        }
+    }
+}
 
 impl Display for ConfirmerError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        match self {
+            ConfirmerError::Walk { path, source } => {
+                write!(f, "Could not walk {:?}: {}", path, source)
+            }
+            ConfirmerError::Open { path, source } => {
+                write!(f, "Could not open {:?}: {}", path, source)
+            }
+            ConfirmerError::Read { path, source } => {
+                write!(f, "Could not read {:?}: {}", path, source)
+            }
+            ConfirmerError::ThreadPanic => write!(f, "A panic occured while calculating hashes."),
+            ConfirmerError::Storage { path, source } => {
+                write!(f, "Could not access {:?}: {}", path, source)
+            }
+            ConfirmerError::InvalidFile { path, source } => {
+                write!(f, "Invalid contents of {:?}: {}", path, source)
+            }
+            ConfirmerError::InvalidSettings(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for ConfirmerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfirmerError::Walk { source, .. }
+            | ConfirmerError::Open { source, .. }
+            | ConfirmerError::Read { source, .. }
+            | ConfirmerError::Storage { source, .. } => Some(source),
+            ConfirmerError::InvalidFile { source, .. } => Some(source.as_ref()),
+            ConfirmerError::ThreadPanic | ConfirmerError::InvalidSettings(_) => None,
+        }
     }
 }
 
-impl From<io::Error> for ConfirmerError {
-    fn from(error: io::Error) -> Self {
-        ConfirmerError(error.to_string())
+impl From<walkdir::Error> for ConfirmerError {
+    fn from(error: walkdir::Error) -> Self {
+        let path = error.path().map(Path::to_path_buf).unwrap_or_default();
+        // Errors other than io errors are filesystem loops
+        let message = error.to_string();
+        let source =
+            error.into_io_error().unwrap_or_else(|| io::Error::new(io::ErrorKind::Other, message));
+        ConfirmerError::Walk { path, source }
     }
 }
@@ -0,0 +1,272 @@
+//! Exclusion of files in source from comparison
+
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+use std::time::SystemTime;
+
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use regex::bytes::RegexSet;
+use walkdir::DirEntry;
+
+use crate::paths::path_to_bytes;
+use crate::ConfirmerError;
+
+/// Names of files with gitignore-style patterns honoured in source, see
+/// [with_ignore_files](crate::CopyConfirmer::with_ignore_files)
+///
+/// Patterns of later files override patterns of earlier files in the same directory.
+pub const IGNORE_FILE_NAMES: [&str; 3] = [".gitignore", ".ignore", ".copconignore"];
+
+/// Exclude pattern
+///
+/// The paths in source directory are matched with the pattern. If the path matches the pattern,
+/// it is excluded from the comparison.
+pub enum ExcludePattern {
+    /// Compare string is anchored to the root of the source directory
+    ///
+    /// Matches only paths starting with contents of MatchPathStart.
+    ///
+    /// Note that the content string should be in form <source_dir + /path/to/sth>
+    MatchPathStart(OsString),
+    /// All paths containing the string are matched
+    MatchEverywhere(String),
+    /// Gitignore-style glob matched against the path relative to the source root
+    ///
+    /// A glob without a slash matches the name of a file or directory at any level (e.g. `*.tmp`),
+    /// other globs are anchored to the source root (e.g. `cache/*.bin`, `**/build`). A matching
+    /// directory excludes all files in it.
+    Glob(String),
+    /// Regular expression matched against the path relative to the source root with components
+    /// separated by `/`, e.g. `^cache/.*\.bin$`
+    Regex(String),
+}
+
+/// Include filter
+///
+/// Only files in source passing all include filters are compared, other files are excluded. Files
+/// with unknown size or modification time (e.g. in manifests loaded from checksum files) pass the
+/// filters on size and modification time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IncludeFilter {
+    /// File name has one of the extensions, given without the leading dot and matched case
+    /// insensitively
+    Extensions(Vec<String>),
+    /// File has at least given number of bytes
+    MinSize(u64),
+    /// File has at most given number of bytes
+    MaxSize(u64),
+    /// File was modified at or after given time
+    ModifiedAfter(SystemTime),
+    /// File was modified before given time
+    ModifiedBefore(SystemTime),
+}
+
+impl IncludeFilter {
+    /// Returns true if file passes the filter
+    ///
+    /// # Arguments
+    /// * `path` - path of the file
+    /// * `size` - size of the file in bytes if known
+    /// * `mtime` - modification time of the file if known
+    pub(crate) fn matches(
+        &self,
+        path: &Path,
+        size: Option<u64>,
+        mtime: Option<SystemTime>,
+    ) -> bool {
+        match self {
+            IncludeFilter::Extensions(extensions) => match path.extension() {
+                Some(extension) => {
+                    extensions.iter().any(|e| extension.to_string_lossy().eq_ignore_ascii_case(e))
+                }
+                None => false,
+            },
+            IncludeFilter::MinSize(min) => size.map_or(true, |size| size >= *min),
+            IncludeFilter::MaxSize(max) => size.map_or(true, |size| size <= *max),
+            IncludeFilter::ModifiedAfter(time) => mtime.map_or(true, |mtime| mtime >= *time),
+            IncludeFilter::ModifiedBefore(time) => mtime.map_or(true, |mtime| mtime < *time),
+        }
+    }
+}
+
+/// Exclude patterns prepared for matching
+pub(crate) struct ExcludeMatcher<'a> {
+    patterns: &'a [ExcludePattern],
+    globs: GlobSet,
+    regexes: RegexSet,
+    /// Match [ExcludePattern::MatchEverywhere] against the relative path instead of the whole path
+    relative_substrings: bool,
+}
+
+impl<'a> ExcludeMatcher<'a> {
+    /// Compile glob and regex patterns
+    ///
+    /// Returns ConfirmerError::InvalidSettings if any of the patterns is invalid
+    pub(crate) fn new(patterns: &'a [ExcludePattern]) -> Result<Self, ConfirmerError> {
+        let mut globs = GlobSetBuilder::new();
+        let mut regexes = vec![];
+        for pattern in patterns {
+            match pattern {
+                ExcludePattern::Glob(glob) => {
+                    globs.add(build_glob(glob).map_err(|e| {
+                        ConfirmerError::InvalidSettings(format!("Invalid glob {:?}: {}", glob, e))
+                    })?);
+                }
+                ExcludePattern::Regex(regex) => regexes.push(regex),
+                _ => {}
+            }
+        }
+        let globs = globs
+            .build()
+            .map_err(|e| ConfirmerError::InvalidSettings(format!("Invalid glob: {}", e)))?;
+        let regexes = RegexSet::new(regexes)
+            .map_err(|e| ConfirmerError::InvalidSettings(format!("Invalid regex: {}", e)))?;
+        Ok(Self { patterns, globs, regexes, relative_substrings: false })
+    }
+
+    /// Match [ExcludePattern::MatchEverywhere] against the path relative to the root, so that the
+    /// pattern does not match the root itself
+    pub(crate) fn with_relative_substrings(self) -> Self {
+        Self { relative_substrings: true, ..self }
+    }
+
+    /// Returns true if there are no patterns
+    pub(crate) fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Returns true if file matches one of excluded patterns
+    ///
+    /// # Arguments
+    /// * `path` - path of the file
+    /// * `relative_path` - path of the file relative to the root
+    pub(crate) fn is_excluded(&self, path: &OsStr, relative_path: &Path) -> bool {
+        let searched_path = match self.relative_substrings {
+            true => relative_path.as_os_str(),
+            false => path,
+        };
+        if is_path_excluded(path, searched_path, self.patterns) {
+            return true;
+        }
+        if !self.globs.is_empty() {
+            let mut ancestors = relative_path.ancestors();
+            if ancestors.any(|p| !p.as_os_str().is_empty() && self.globs.is_match(p)) {
+                return true;
+            }
+        }
+        if !self.regexes.is_empty() {
+            let components: Vec<_> = relative_path
+                .components()
+                .map(|c| path_to_bytes(Path::new(c.as_os_str())))
+                .collect();
+            if self.regexes.is_match(&components.join(&b'/')) {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Ignore files of directories on the current path of a directory walk
+///
+/// Patterns of ignore files in deeper directories take precedence, as in git.
+pub(crate) struct IgnoreStack {
+    /// Matchers with depth of the directory they were loaded from
+    matchers: Vec<(usize, Gitignore)>,
+}
+
+impl IgnoreStack {
+    pub(crate) fn new() -> Self {
+        Self { matchers: vec![] }
+    }
+
+    /// Returns true if the walked entry is ignored
+    ///
+    /// Entries must be passed in the order of the walk, so that matchers of directories already
+    /// left can be dropped.
+    pub(crate) fn is_ignored(&mut self, entry: &DirEntry) -> bool {
+        while self.matchers.last().map_or(false, |(depth, _)| *depth >= entry.depth()) {
+            self.matchers.pop();
+        }
+        let is_dir = entry.file_type().is_dir();
+        for (_, matcher) in self.matchers.iter().rev() {
+            let matched = matcher.matched(entry.path(), is_dir);
+            if !matched.is_none() {
+                return matched.is_ignore();
+            }
+        }
+        false
+    }
+
+    /// Load ignore files of a walked directory
+    ///
+    /// Returns ConfirmerError::InvalidFile if an ignore file cannot be read or parsed
+    pub(crate) fn push_dir(&mut self, entry: &DirEntry) -> Result<(), ConfirmerError> {
+        let mut builder = GitignoreBuilder::new(entry.path());
+        for name in IGNORE_FILE_NAMES {
+            let path = entry.path().join(name);
+            if !path.is_file() {
+                continue;
+            }
+            if let Some(e) = builder.add(&path) {
+                return Err(ConfirmerError::InvalidFile { path, source: e.into() });
+            }
+        }
+        let matcher = builder.build().map_err(|e| ConfirmerError::InvalidFile {
+            path: entry.path().to_path_buf(),
+            source: e.into(),
+        })?;
+        if !matcher.is_empty() {
+            self.matchers.push((entry.depth(), matcher));
+        }
+        Ok(())
+    }
+}
+
+/// Build glob with gitignore-like anchoring, see [ExcludePattern::Glob]
+fn build_glob(glob: &str) -> Result<Glob, globset::Error> {
+    let glob = glob.trim_end_matches('/');
+    let glob = match glob.strip_prefix('/') {
+        Some(anchored) => anchored.to_string(),
+        None if !glob.contains('/') => format!("**/{}", glob),
+        None => glob.to_string(),
+    };
+    GlobBuilder::new(&glob).literal_separator(true).build()
+}
+
+/// Returns true if path contains one of excluded substring patterns
+///
+/// [MatchPathStart](ExcludePattern::MatchPathStart) is matched against `path`,
+/// [MatchEverywhere](ExcludePattern::MatchEverywhere) against `searched_path`. The paths are
+/// compared as raw bytes, so they need not be valid UTF-8
+fn is_path_excluded(
+    path: &OsStr,
+    searched_path: &OsStr,
+    excluded_patterns: &[ExcludePattern],
+) -> bool {
+    use ExcludePattern::*;
+    let path = path_to_bytes(Path::new(path));
+    let searched_path = path_to_bytes(Path::new(searched_path));
+    for pattern in excluded_patterns {
+        match pattern {
+            MatchEverywhere(part) => {
+                let part = part.as_bytes();
+                if part.is_empty() || searched_path.windows(part.len()).any(|window| window == part)
+                {
+                    return true;
+                }
+            }
+
+            MatchPathStart(part) => {
+                if path.starts_with(&path_to_bytes(Path::new(part))) {
+                    return true;
+                }
+            }
+
+            Glob(_) | Regex(_) => {}
+        }
+    }
+
+    false
+}
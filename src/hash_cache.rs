@@ -0,0 +1,260 @@
+//! Persistent cache of file checksums
+//!
+//! Checksums are stored in a single json file keyed by absolute path of the file. A cached
+//! checksum is reused only if size, modification time and inode of the file are unchanged and it
+//! was calculated with the same hash algorithm. Checksums of files that disappeared from a walked
+//! directory are dropped, so that the cache does not keep growing with deleted and moved files.
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::{ConfirmerError, HashAlgorithm};
+
+/// Version of the cache file format
+const CACHE_VERSION: u32 = 1;
+
+/// Metadata of a file that must match for a cached checksum to be used
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct FileStamp {
+    pub size: u64,
+    pub mtime: Option<SystemTime>,
+    pub inode: u64,
+}
+
+/// Cached checksum of a single file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CacheEntry {
+    size: u64,
+    /// Modification time as seconds and nanoseconds since unix epoch
+    mtime: (u64, u32),
+    inode: u64,
+    algorithm: String,
+    hash: String,
+    /// Time of the checksum calculation in seconds since unix epoch
+    hashed_at: u64,
+}
+
+/// Contents of the cache file
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    entries: HashMap<String, CacheEntry>,
+}
+
+/// Persistent cache of file checksums
+///
+/// Use [open](HashCache::open) to load the cache and register it with
+/// [with_hash_cache](crate::CopyConfirmer::with_hash_cache). The cache is saved back to its file
+/// after each comparison.
+pub struct HashCache {
+    path: PathBuf,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+    max_age: Option<Duration>,
+    modified: AtomicBool,
+}
+
+impl HashCache {
+    /// Load cache from file, start with an empty cache if the file does not exist
+    ///
+    /// # Arguments
+    /// * `path` - path to the cache file
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, ConfirmerError> {
+        let path = path.as_ref().to_path_buf();
+        let entries = match File::open(&path) {
+            Ok(file) => {
+                let cache_file: CacheFile =
+                    serde_json::from_reader(BufReader::new(file)).map_err(|e| {
+                        ConfirmerError::InvalidFile { path: path.clone(), source: e.into() }
+                    })?;
+                if cache_file.version != CACHE_VERSION {
+                    return Err(ConfirmerError::InvalidFile {
+                        path,
+                        source: format!("Unsupported version {}", cache_file.version).into(),
+                    });
+                }
+                cache_file.entries
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(source) => return Err(ConfirmerError::Storage { path, source }),
+        };
+        Ok(Self {
+            path,
+            entries: Mutex::new(entries),
+            max_age: None,
+            modified: AtomicBool::new(false),
+        })
+    }
+
+    /// Recalculate checksums older than `max_age` even if the file did not change
+    ///
+    /// Useful to detect silent corruption of files that keep their metadata.
+    pub fn with_max_age(self, max_age: Duration) -> Self {
+        let mut modifiable = self;
+        modifiable.max_age = Some(max_age);
+        modifiable
+    }
+
+    /// Number of cached checksums
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Returns true if there are no cached checksums
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Write the cache to its file if there are any new checksums
+    pub fn save(&self) -> Result<(), ConfirmerError> {
+        if !self.modified.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        self._write().map_err(|source| ConfirmerError::Storage { path: self.path.clone(), source })
+    }
+
+    /// Write the cache to its file, see [save](HashCache::save)
+    fn _write(&self) -> io::Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Write to a temporary file first, so that the cache is not corrupted on interruption
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let entries = self.entries.lock().unwrap();
+        let cache_file = CacheFile { version: CACHE_VERSION, entries: entries.clone() };
+        drop(entries);
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer(&mut writer, &cache_file)?;
+        writer.flush()?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    /// Get cached checksum of the file if it is still valid
+    ///
+    /// # Arguments
+    /// * `path` - path to the file
+    /// * `stamp` - current metadata of the file
+    /// * `algorithm` - hash algorithm of the requested checksum
+    pub(crate) fn get(
+        &self,
+        path: &OsStr,
+        stamp: &FileStamp,
+        algorithm: HashAlgorithm,
+    ) -> Option<String> {
+        let key = cache_key(path)?;
+        let mtime = unix_time(stamp.mtime?)?;
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(&key)?;
+
+        let is_fresh = match self.max_age {
+            Some(max_age) => {
+                let hashed_at = UNIX_EPOCH + Duration::from_secs(entry.hashed_at);
+                SystemTime::now().duration_since(hashed_at).map_or(true, |age| age <= max_age)
+            }
+            None => true,
+        };
+        let is_valid = entry.size == stamp.size
+            && entry.mtime == mtime
+            && entry.inode == stamp.inode
+            && entry.algorithm == algorithm.name();
+        (is_fresh && is_valid).then(|| entry.hash.clone())
+    }
+
+    /// Remove checksums of files in `root` that no longer exist
+    ///
+    /// Files found by the walk of `root` are kept without being checked again, only the other
+    /// cached files in it are looked up on disk.
+    ///
+    /// # Arguments
+    /// * `root` - walked directory
+    /// * `found` - paths of the files found in `root`
+    pub(crate) fn prune<'a>(&self, root: &OsStr, found: impl Iterator<Item = &'a OsStr>) {
+        let mut prefix = match cache_key(root) {
+            Some(prefix) => prefix,
+            None => return,
+        };
+        if !prefix.ends_with(MAIN_SEPARATOR) {
+            prefix.push(MAIN_SEPARATOR);
+        }
+        let found: HashSet<String> = found.filter_map(cache_key).collect();
+        let mut entries = self.entries.lock().unwrap();
+        let count = entries.len();
+        entries.retain(|key, _| {
+            !key.starts_with(&prefix) || found.contains(key) || fs::symlink_metadata(key).is_ok()
+        });
+        if entries.len() != count {
+            debug!("Removed {} stale checksums from cache", count - entries.len());
+            self.modified.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Store checksum of the file
+    ///
+    /// Files with non-UTF-8 paths or without modification time are not cached.
+    ///
+    /// # Arguments
+    /// * `path` - path to the file
+    /// * `stamp` - metadata of the file at the time of the checksum calculation
+    /// * `algorithm` - hash algorithm of the checksum
+    /// * `hash` - the checksum
+    pub(crate) fn insert(
+        &self,
+        path: &OsStr,
+        stamp: &FileStamp,
+        algorithm: HashAlgorithm,
+        hash: &str,
+    ) {
+        let (key, mtime) = match (cache_key(path), stamp.mtime.and_then(unix_time)) {
+            (Some(key), Some(mtime)) => (key, mtime),
+            _ => return,
+        };
+        let hashed_at = unix_time(SystemTime::now()).map_or(0, |(secs, _)| secs);
+        let entry = CacheEntry {
+            size: stamp.size,
+            mtime,
+            inode: stamp.inode,
+            algorithm: algorithm.name().to_string(),
+            hash: hash.to_string(),
+            hashed_at,
+        };
+        self.entries.lock().unwrap().insert(key, entry);
+        self.modified.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Get default location of the cache file in the user's cache directory
+pub fn default_cache_path() -> Option<PathBuf> {
+    let cache_dir = match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+    };
+    Some(cache_dir.join("copcon").join("hashes.json"))
+}
+
+/// Absolute path of the file as a string, so that the cache does not depend on working directory
+fn cache_key(path: &OsStr) -> Option<String> {
+    let path = Path::new(path);
+    let absolute = match path.is_absolute() {
+        true => path.to_path_buf(),
+        false => env::current_dir().ok()?.join(path),
+    };
+    absolute.into_os_string().into_string().ok()
+}
+
+/// Convert time to seconds and nanoseconds since unix epoch
+fn unix_time(time: SystemTime) -> Option<(u64, u32)> {
+    let since_epoch = time.duration_since(UNIX_EPOCH).ok()?;
+    Some((since_epoch.as_secs(), since_epoch.subsec_nanos()))
+}
@@ -2,9 +2,14 @@
 //!
 //! Compares directories (`source` and multiple dirs in `destinations`) by creating hash of each
 //! file in `source` and then checking that there is at least one file with the same hash in one of
-//! directories in `destinations`. If all files in `source` are in at least one of the destination
-//! directories, we return `ConfirmerResult::Ok`, otherwise we list all the missing files in
-//! `ConfirmerResult::MissingFiles`.
+//! directories in `destinations`. The returned [ConfirmerResult] lists both the files found in the
+//! destination directories and the files missing from all of them.
+//!
+//! Destinations are walked while their files get hashed and the comparison stops once every file
+//! in source is found. Files in destinations are hashed only if there is a file of the same size in
+//! source. With [with_all_copies](CopyConfirmer::with_all_copies) or
+//! [with_extra_files](CopyConfirmer::with_extra_files) all directories are walked first and source
+//! files without a destination file of the same size are reported missing right away.
 //!
 //! # Example usage
 //! Suppose we have a directory structure:
@@ -22,108 +27,729 @@
 //!
 //! # fn main() -> Result<(), ConfirmerError> {
 //! let cc = CopyConfirmer::new(1);
-//! let missing_files = cc.compare("tests/fixtures/dir_A",
-//!                                &["tests/fixtures/dir_B"])?;
+//! let result = cc.compare("tests/fixtures/dir_A",
+//!                         &["tests/fixtures/dir_B"])?;
 //!
-//! let expected_missing = vec!["tests/fixtures/dir_A/bar.txt".into()];
-//! assert_eq!(missing_files, ConfirmerResult::MissingFiles(expected_missing));
+//! let expected_missing: Vec<std::ffi::OsString> = vec!["tests/fixtures/dir_A/bar.txt".into()];
+//! assert!(!result.is_complete());
+//! assert_eq!(result.missing, expected_missing);
+//! assert_eq!(result.found.len(), 1);
 //! # Ok(())
 //! # }
 //! ```
 //!
-//! We can show a progress bar by setting [with_progress_bar](CopyConfirmer::with_progress_bar). We
-//! can exclude files from comparison with
-//! [add_excluded_pattern](CopyConfirmer::add_excluded_pattern).
+//! Instead of a directory, the source or any destination can be a [Manifest] created by
+//! [create_manifest](CopyConfirmer::create_manifest), see
+//! [compare_locations](CopyConfirmer::compare_locations).
+//!
+//! We can show a progress bar by setting [with_progress_bar](CopyConfirmer::with_progress_bar) or
+//! follow the comparison with our own [Observer] registered by
+//! [add_observer](CopyConfirmer::add_observer). We can exclude files from comparison with
+//! [add_excluded_pattern](CopyConfirmer::add_excluded_pattern) or with `.gitignore` files in
+//! source by setting [with_ignore_files](CopyConfirmer::with_ignore_files).
 
 mod checksum;
+mod checksum_file;
 mod copcon_error;
+mod exclude;
+mod hash_cache;
+mod manifest;
+mod observer;
+mod paths;
+mod progress;
+mod walk;
+mod workers;
 
 use std::cell::Cell;
-use std::collections::HashMap;
+use std::cmp::max;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
 use std::ffi::{OsStr, OsString};
-use std::io::Result as IoResult;
-use std::sync::mpsc::{channel, Receiver, Sender};
-use std::{thread, time};
+use std::fs::Metadata;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
-use indicatif::{ProgressBar, ProgressStyle};
-use threadpool::ThreadPool;
-use walkdir::WalkDir;
+use log::debug;
+use walkdir::DirEntry;
 
 use checksum::*;
+pub use checksum::{CustomAlgorithm, FileHasher, HashAlgorithm};
 pub use copcon_error::ConfirmerError;
-use serde::{ser::SerializeSeq, Serialize, Serializer};
+use exclude::ExcludeMatcher;
+pub use exclude::{ExcludePattern, IncludeFilter, IGNORE_FILE_NAMES};
+use hash_cache::FileStamp;
+pub use hash_cache::{default_cache_path, HashCache};
+pub use manifest::{Manifest, ManifestEntry};
+use observer::Observers;
+pub use observer::{Observer, ProgressBarObserver};
+use paths::serialize_paths;
+pub use progress::{Phase, Progress, ProgressSnapshot};
+use serde::Serialize;
+use walk::{list_files, list_locations, visit_files, BrokenLinks, Listing, WalkSettings};
+use workers::{Job, Workers};
 
-/// Indicates whether there are files missing in destination dirs
-#[derive(Debug, PartialEq)]
-pub enum ConfirmerResult {
-    /// Indicates all files in source are in at least one destination dir
+/// Outcome of a comparison
+///
+/// Holds both the files found in destinations and the files missing from them, use
+/// [is_complete](ConfirmerResult::is_complete) to check that all files in source have a copy.
+#[derive(Debug)]
+pub struct ConfirmerResult {
+    /// Files in source found in destinations
     ///
     /// Contains HashMap with key ~ checksum of a file and value ~ [FileFound](FileFound) struct
     /// that contains files corresponding to that checksum in source and destination directories.
-    Ok(HashMap<String, FileFound>),
-    /// Contains files in source that are missing from all destinations
-    MissingFiles(Vec<OsString>),
+    pub found: HashMap<String, FileFound>,
+    /// Files in source that are missing from all destinations, sorted by path
+    pub missing: Vec<OsString>,
+    /// Files in source whose content is in destinations only under a different relative path
+    ///
+    /// Has the same structure as [found](ConfirmerResult::found). Can be non-empty only with
+    /// [with_strict_paths](CopyConfirmer::with_strict_paths).
+    pub moved: HashMap<String, FileFound>,
+    /// Files in source found in fewer destinations than required
+    ///
+    /// Has the same structure as [found](ConfirmerResult::found), the files are listed in both.
+    /// Can be non-empty only with [with_min_copies](CopyConfirmer::with_min_copies).
+    pub under_replicated: HashMap<String, FileFound>,
+    /// Files in source found in each destination
+    ///
+    /// Has one entry per destination in the order they were given. A file found in several
+    /// destinations is counted in each of them. Destinations are walked only until all files are
+    /// found, use [with_all_copies](CopyConfirmer::with_all_copies) to count every copy. With
+    /// [with_strict_paths](CopyConfirmer::with_strict_paths) only copies at the same path count.
+    pub coverage: Vec<DestinationCoverage>,
+    /// Files in each destination without a counterpart in source, sorted by path
+    ///
+    /// Has one entry per destination in the order they were given. Collected only with
+    /// [with_extra_files](CopyConfirmer::with_extra_files), empty otherwise.
+    pub extra: Vec<Vec<OsString>>,
+    /// Files in source excluded from the comparison by excluded patterns
+    ///
+    /// With [with_ignore_files](CopyConfirmer::with_ignore_files) ignored directories are listed
+    /// instead of the files in them.
+    pub excluded: Vec<OsString>,
+    /// Files in source or destinations that could not be read, sorted by path
+    ///
+    /// Can be non-empty only with [ErrorPolicy::SkipAndReport].
+    pub unreadable: Vec<UnreadableFile>,
+    /// Symbolic links in source or destinations pointing to nonexistent files, sorted by path
+    ///
+    /// Collected with [SymlinkPolicy::Follow] and [SymlinkPolicy::CompareTarget]. The links are
+    /// left out of the comparison with [SymlinkPolicy::Follow].
+    pub dangling_links: Vec<OsString>,
+    /// Symbolic links in source or destinations pointing to one of their parent directories,
+    /// sorted by path
+    ///
+    /// Collected with [SymlinkPolicy::Follow], the links are not walked.
+    pub symlink_loops: Vec<OsString>,
+    /// Groups of paths in source and destinations that are hardlinks of the same file
+    ///
+    /// Each group is sorted, the groups are sorted by their first path. Each file is hashed only
+    /// once for all its hardlinks. Files in destinations are listed only if they were walked
+    /// before all files in source were found, see [compare](CopyConfirmer::compare).
+    pub hardlinks: Vec<Vec<OsString>>,
+    /// Statistics of the comparison
+    pub stats: ComparisonStats,
+}
+
+impl ConfirmerResult {
+    /// Returns true if all files in source are in enough destinations and no file was skipped
+    /// because of an error
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+            && self.moved.is_empty()
+            && self.under_replicated.is_empty()
+            && self.unreadable.is_empty()
+    }
+}
+
+/// Statistics of a comparison
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ComparisonStats {
+    /// Number of files in source, excluded files are not counted
+    pub source_files: u64,
+    /// Total size of files in source, files of unknown size are not counted
+    pub source_bytes: u64,
+    /// Number of files walked in all destinations, see [compare](CopyConfirmer::compare)
+    pub dest_files: u64,
+    /// Total size of files walked in all destinations, files of unknown size are not counted
+    pub dest_bytes: u64,
+    /// Number of files whose checksum was calculated, including checksums of file ends
+    pub hashed_files: u64,
+    /// Duration of the comparison
+    pub elapsed: Duration,
+}
+
+/// File that could not be read and was left out of the comparison
+#[derive(Debug)]
+pub struct UnreadableFile {
+    /// Path of the file
+    pub path: OsString,
+    /// Error encountered when reading the file
+    pub error: ConfirmerError,
+}
+
+impl PartialEq for UnreadableFile {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path && self.error.to_string() == other.error.to_string()
+    }
+}
+
+/// What to do when a file cannot be read
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Stop the comparison and return the error
+    #[default]
+    Abort,
+    /// Leave the file out of the comparison and list it in
+    /// [unreadable](ConfirmerResult::unreadable)
+    ///
+    /// Errors not related to a single file, e.g. a missing source directory, still abort the
+    /// comparison.
+    SkipAndReport,
+}
+
+/// What to do with symbolic links in source and destinations
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Leave symbolic links out of the comparison
+    #[default]
+    Skip,
+    /// Compare files the links point to and walk linked directories
+    ///
+    /// Links pointing to one of their parent directories are not walked again and are reported
+    /// in [symlink_loops](ConfirmerResult::symlink_loops).
+    Follow,
+    /// Compare the links themselves by their target paths, as they are copied by e.g. `cp -P`
+    ///
+    /// A link is found only if there is a link with the same target path in a destination.
+    CompareTarget,
 }
 
 /// Holds information on all paths in source and destinations that contain the same file
-#[derive(Debug, PartialEq, Serialize)]
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
 pub struct FileFound {
     /// Paths of same files in source
-    #[serde(serialize_with = "osstring_serialize")]
+    #[serde(serialize_with = "serialize_paths")]
     pub src_paths: Vec<OsString>,
     /// Paths of same files in destinations
-    #[serde(serialize_with = "osstring_serialize")]
+    #[serde(serialize_with = "serialize_paths")]
     pub dest_paths: Vec<OsString>,
+    /// Index of the destination of each path in `dest_paths`, in the order the destinations
+    /// were given
+    pub dest_locations: Vec<usize>,
+    /// Index in `src_paths` of the file each path in `dest_paths` is a copy of, filled only with
+    /// [with_strict_paths](CopyConfirmer::with_strict_paths). Otherwise each path in
+    /// `dest_paths` is a copy of all paths in `src_paths`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub dest_sources: Vec<usize>,
 }
 
-/// Exclude pattern
-///
-/// The paths in source directory are matched with the pattern. If the path contains the pattern
-/// string, it is excluded from the comparison.
-pub enum ExcludePattern {
-    /// Compare string is anchored to the root of the source directory
-    ///
-    /// Matches only paths starting with contents of MatchPathStart.
-    ///
-    /// Note that the content string should be in form <source_dir + /path/to/sth>
-    MatchPathStart(String),
-    /// All paths containing the string are matched
-    MatchEverywhere(String),
-    // TODO: Add MatchPathFromSource or sth that anchors the contents to source_dir? This would
-    // work kinda similarly to MatchPathStart
-    // TODO: Add wildcards?
-}
-
-/// Helper function for serialisation of paths
-fn osstring_serialize<S>(hs: &[OsString], s: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    let mut seq = s.serialize_seq(Some(hs.len()))?;
-    for item in hs.iter() {
-        let stringy: String = item
-            .to_owned()
-            .into_string()
-            .unwrap_or_else(|osstr| format!("Error decoding this: {:?}", osstr));
-        seq.serialize_element(&stringy)?;
+impl FileFound {
+    /// Number of destinations with a copy of the file, the lowest of the files in source
+    pub fn copies(&self) -> usize {
+        if self.dest_sources.is_empty() {
+            let locations: HashSet<&usize> = self.dest_locations.iter().collect();
+            return locations.len();
+        }
+        self.copy_locations().iter().map(Vec::len).min().unwrap_or(0)
+    }
+
+    /// Sorted indices of the destinations with a copy of each file in `src_paths`
+    pub fn copy_locations(&self) -> Vec<Vec<usize>> {
+        if self.dest_sources.is_empty() {
+            let mut locations = self.dest_locations.clone();
+            locations.sort_unstable();
+            locations.dedup();
+            return vec![locations; self.src_paths.len()];
+        }
+        let mut locations = vec![vec![]; self.src_paths.len()];
+        for (source, location) in self.dest_sources.iter().zip(&self.dest_locations) {
+            locations[*source].push(*location);
+        }
+        for source_locations in &mut locations {
+            source_locations.sort_unstable();
+            source_locations.dedup();
+        }
+        locations
+    }
+}
+
+/// Files in source with a copy in a single destination
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DestinationCoverage {
+    /// Number of files in source with a copy in the destination
+    pub files: u64,
+    /// Total size of files in source with a copy in the destination, files of unknown size are
+    /// not counted
+    pub bytes: u64,
+}
+
+/// Directory tree to be compared
+pub enum Location {
+    /// Directory that gets walked and hashed
+    Dir(OsString),
+    /// Manifest with checksums of files in a directory tree, the files are not accessed
+    Manifest(Manifest),
+}
+
+impl From<Manifest> for Location {
+    fn from(manifest: Manifest) -> Self {
+        Location::Manifest(manifest)
+    }
+}
+
+/// File found when walking a directory
+#[derive(Debug)]
+struct FileEntry {
+    path: OsString,
+    /// Path relative to the root of the directory or manifest
+    relative_path: PathBuf,
+    /// Index of the destination the file is in, zero for files in source
+    location: usize,
+    /// Size of the file, unknown for some manifests
+    size: Option<u64>,
+    mtime: Option<SystemTime>,
+    /// Device and inode number of the file, zero if unknown
+    device: u64,
+    inode: u64,
+    /// Number of hardlinks of the file, zero if unknown
+    links: u64,
+    /// Checksum of the whole file if it is already known
+    hash: Option<String>,
+    /// Other paths of the same file, they share the checksum of this file
+    hardlinks: Vec<FileEntry>,
+}
+
+impl FileEntry {
+    /// Collect path and metadata of a file found when walking a directory
+    ///
+    /// Links followed with [SymlinkPolicy::Follow] get the metadata of their targets, but no
+    /// inode, so that they are not taken for hardlinks of the targets.
+    fn from_dir_entry(item: DirEntry, root: &Path) -> Result<Self, ConfirmerError> {
+        let metadata = item.metadata()?;
+        let (device, inode, links) = match item.path_is_symlink() {
+            true => (0, 0, 0),
+            false => (get_device(&metadata), get_inode(&metadata), get_links(&metadata)),
+        };
+        Ok(Self {
+            relative_path: item.path().strip_prefix(root).unwrap_or(item.path()).to_path_buf(),
+            location: 0,
+            size: Some(metadata.len()),
+            mtime: metadata.modified().ok(),
+            device,
+            inode,
+            links,
+            path: item.into_path().into_os_string(),
+            hash: None,
+            hardlinks: vec![],
+        })
+    }
+
+    /// Collect a symbolic link compared by its target, see [SymlinkPolicy::CompareTarget]
+    ///
+    /// The checksum of the target path is calculated right away, so the link is never read as
+    /// a file.
+    fn from_symlink(
+        item: DirEntry,
+        root: &Path,
+        algorithm: HashAlgorithm,
+    ) -> Result<Self, ConfirmerError> {
+        let metadata = item.metadata()?;
+        let target = std::fs::read_link(item.path())
+            .map_err(|source| ConfirmerError::Read { path: item.path().into(), source })?;
+        let target = paths::path_to_bytes(&target);
+        Ok(Self {
+            relative_path: item.path().strip_prefix(root).unwrap_or(item.path()).to_path_buf(),
+            location: 0,
+            size: Some(target.len() as u64),
+            mtime: metadata.modified().ok(),
+            device: get_device(&metadata),
+            inode: get_inode(&metadata),
+            links: get_links(&metadata),
+            path: item.into_path().into_os_string(),
+            hash: Some(get_symlink_checksum(&target, algorithm)),
+            hardlinks: vec![],
+        })
+    }
+
+    /// Get file listed in a manifest
+    fn from_manifest_entry(root: &Path, entry: &ManifestEntry) -> Self {
+        Self {
+            path: root.join(&entry.path).into_os_string(),
+            relative_path: entry.path.clone(),
+            location: 0,
+            size: entry.size,
+            mtime: entry.mtime,
+            device: 0,
+            inode: 0,
+            links: 0,
+            hash: Some(entry.hash.clone()),
+            hardlinks: vec![],
+        }
+    }
+
+    /// Split the file into itself and its hardlinks
+    fn into_links(mut self) -> Vec<FileEntry> {
+        let mut links = std::mem::take(&mut self.hardlinks);
+        links.insert(0, self);
+        links
+    }
+
+    /// Device and inode number shared by all hardlinks of the file, none if unknown
+    fn inode_key(&self) -> Option<(u64, u64)> {
+        (self.inode != 0).then_some((self.device, self.inode))
+    }
+
+    /// Metadata identifying the version of the file for hash cache
+    fn stamp(&self) -> FileStamp {
+        FileStamp { size: self.size.unwrap_or_default(), mtime: self.mtime, inode: self.inode }
+    }
+}
+
+/// Checksum calculated for a file
+#[derive(Clone, Copy)]
+enum HashStage {
+    /// Checksum of the first and the last `block_size` bytes of the file
+    Partial { block_size: u64 },
+    /// Checksum of the whole file
+    Full,
+}
+
+impl HashStage {
+    /// Number of bytes read to calculate the checksum of the file, zero if unknown
+    fn bytes_to_read(&self, file: &FileEntry) -> u64 {
+        match self {
+            HashStage::Partial { block_size } => file.size.unwrap_or(0).min(2 * block_size),
+            HashStage::Full if file.hash.is_some() => 0,
+            HashStage::Full => file.size.unwrap_or(0),
+        }
     }
-    seq.end()
 }
 
 /// type for mpsc channel in CopyConfirmer
-type HashResult = (OsString, IoResult<String>);
+type HashResult = (FileEntry, Result<String, ConfirmerError>);
+
+/// Message sent to the channel of file hashes
+enum HashMessage {
+    /// Result of a job, none if the job was skipped
+    Hashed(Option<HashResult>),
+    /// Hardlink of a file sent to the workers earlier by a walk of destinations, it gets the
+    /// checksum of that file
+    Link(FileEntry),
+    /// A walk of destinations ended after sending `sent` jobs and links
+    WalkEnded { sent: u64 },
+}
+
+/// Checksums of ends of large files in source, see [HashStage::Partial]
+///
+/// Files in destinations of the same size as these files are hashed whole only if the checksum
+/// of their ends matches.
+struct SourceEnds {
+    block_size: u64,
+    /// Sizes of files in source, all files of these sizes have checksums of their ends
+    sizes: HashSet<u64>,
+    /// Sizes of the files with checksums of their ends
+    hashes: HashSet<(u64, String)>,
+}
+
+impl SourceEnds {
+    /// Checksum of ends calculated for `file` before its checksum of the whole file, none if the
+    /// file is hashed whole right away
+    fn stage(&self, file: &FileEntry) -> Option<HashStage> {
+        file.size.filter(|size| self.sizes.contains(size))?;
+        file.hash.is_none().then_some(HashStage::Partial { block_size: self.block_size })
+    }
+
+    /// Returns true if a file in source has the size of `file` and checksum of ends `hash`
+    fn matches(&self, file: &FileEntry, hash: &str) -> bool {
+        file.size.map_or(false, |size| self.hashes.contains(&(size, hash.to_string())))
+    }
+}
+
+/// Creates jobs calculating checksums of files and sending them to the channel of file hashes
+///
+/// Holds everything the jobs need, so that they can be created by the threads walking
+/// destinations as well.
+#[derive(Clone)]
+struct HashJobs {
+    sender: Sender<HashMessage>,
+    /// Flag to skip the calculation
+    cancelled: Arc<AtomicBool>,
+    cache: Option<Arc<HashCache>>,
+    hashed_files: Arc<AtomicU64>,
+    observers: Observers,
+    algorithm: HashAlgorithm,
+    /// Files matching none of these ends are ruled out before they are hashed whole
+    source_ends: Option<Arc<SourceEnds>>,
+}
+
+impl HashJobs {
+    /// Checksum of ends calculated before checksum of the whole file, see [SourceEnds]
+    fn ends_stage(&self, file: &FileEntry, stage: HashStage) -> Option<HashStage> {
+        match stage {
+            HashStage::Full => self.source_ends.as_ref()?.stage(file),
+            HashStage::Partial { .. } => None,
+        }
+    }
+
+    /// Number of bytes read by the job of the file, zero if unknown
+    fn bytes_to_read(&self, file: &FileEntry, stage: HashStage) -> u64 {
+        let ends = self.ends_stage(file, stage).map_or(0, |ends| ends.bytes_to_read(file));
+        ends + stage.bytes_to_read(file)
+    }
+
+    /// Create job calculating checksum of the file and sending it to the channel of file hashes
+    ///
+    /// Bytes of files not read, e.g. with checksums from the cache, are counted once the job
+    /// ends. Every job sends exactly one message: None if it was skipped because `cancelled` is
+    /// set or the file was ruled out by the checksum of its ends, [ConfirmerError::ThreadPanic]
+    /// if the calculation panicked.
+    ///
+    /// # Arguments
+    /// * `file` - file to calculate checksum of
+    /// * `stage` - checksum to calculate
+    fn job(&self, file: FileEntry, stage: HashStage) -> Job {
+        let jobs = self.clone();
+        Box::new(move || {
+            if jobs.cancelled.load(Ordering::Relaxed) {
+                jobs.sender.send(HashMessage::Hashed(None)).expect("Could not send file hash");
+                return;
+            }
+            let calculate = || {
+                let bytes = jobs.bytes_to_read(&file, stage);
+                jobs.observers.hash_started(&file.path, bytes);
+                let read = Cell::new(0);
+                let on_read = |count| {
+                    read.set(read.get() + count);
+                    jobs.observers.bytes_hashed(count);
+                };
+                let hash = jobs._calculate(&file, stage, &on_read);
+                jobs.observers.bytes_hashed(bytes.saturating_sub(read.get()));
+                let checksum = hash.as_ref().map(|(checksum, _)| checksum.as_str());
+                jobs.observers.hash_finished(&file.path, checksum);
+                hash
+            };
+            let message = match panic::catch_unwind(AssertUnwindSafe(calculate)) {
+                Ok(Ok((_, false))) => None,
+                Ok(Ok((hash, true))) => Some((file, Ok(hash))),
+                Ok(Err(e)) => Some((file, Err(e))),
+                Err(_) => Some((file, Err(ConfirmerError::ThreadPanic))),
+            };
+            jobs.sender.send(HashMessage::Hashed(message)).expect("Could not send file hash")
+        })
+    }
+
+    /// Calculate checksum of the file, first of its ends if needed
+    ///
+    /// Returns the checksum with false if the file is ruled out by the checksum of its ends, in
+    /// which case it is the checksum of the ends.
+    fn _calculate(
+        &self,
+        file: &FileEntry,
+        stage: HashStage,
+        on_read: &dyn Fn(u64),
+    ) -> Result<(String, bool), ConfirmerError> {
+        if let Some(ends_stage) = self.ends_stage(file, stage) {
+            self.hashed_files.fetch_add(1, Ordering::Relaxed);
+            let ends = get_hash(file, ends_stage, self.algorithm, None, on_read)?;
+            let source_ends = self.source_ends.as_ref().expect("Ends are checked against source");
+            if !source_ends.matches(file, &ends) {
+                return Ok((ends, false));
+            }
+        }
+        // Checksums already known, e.g. from manifests, are not calculated
+        if matches!(stage, HashStage::Partial { .. }) || file.hash.is_none() {
+            self.hashed_files.fetch_add(1, Ordering::Relaxed);
+        }
+        let hash = get_hash(file, stage, self.algorithm, self.cache.as_deref(), on_read)?;
+        Ok((hash, true))
+    }
+}
+
+/// Destination walked while its files were hashed
+#[derive(Default)]
+struct DestinationWalk {
+    /// Paths left out of the walk, without the files
+    listing: Listing,
+    /// Number and total size of files found
+    files: u64,
+    bytes: u64,
+    /// Device and inode number with path of each file with several hardlinks
+    links: Vec<((u64, u64), OsString)>,
+}
+
+/// Adds jobs for files in destinations as they are walked, shared by the threads walking them
+struct DestinationWalker<'a> {
+    workers: &'a Workers,
+    /// Sizes of files in source, files of other sizes are not hashed
+    source_sizes: HashSet<Option<u64>>,
+    /// Checksums of files in source with several hardlinks by their device and inode
+    source_inodes: HashMap<(u64, u64), String>,
+    /// Device and inode number of files with several hardlinks sent to the workers
+    sent_inodes: Mutex<HashSet<(u64, u64)>>,
+}
+
+impl DestinationWalker<'_> {
+    /// Walk destinations one by one and add jobs calculating checksums of their files
+    ///
+    /// A slot is taken in `slots` before each job or hardlink is sent, so that the walk waits
+    /// while too many of them are left to the reader of the channel of file hashes. The walk
+    /// stops once the hashing is cancelled. [HashMessage::WalkEnded] is sent once the walk ends,
+    /// even if it failed or panicked, errors cancel the hashing.
+    ///
+    /// Returns the walks in the order of `destinations`.
+    ///
+    /// # Arguments
+    /// * `destinations` - index of each destination in the comparison with its location and
+    ///   settings of the walk
+    /// * `jobs` - creates the jobs
+    /// * `slots` - bounded channel of the jobs and hardlinks not handled yet
+    fn walk(
+        &self,
+        destinations: &[(usize, &(&Location, WalkSettings))],
+        jobs: HashJobs,
+        slots: SyncSender<()>,
+    ) -> Result<Vec<DestinationWalk>, ConfirmerError> {
+        let mut sent = 0;
+        let walk_all = || {
+            destinations
+                .iter()
+                .map(|(index, (location, settings))| {
+                    let mut walk = DestinationWalk::default();
+                    let listing = visit_files(location, settings, &mut |file| {
+                        if jobs.cancelled.load(Ordering::Relaxed) {
+                            return false;
+                        }
+                        let file = FileEntry { location: *index, ..file };
+                        if self._add_file(file, &jobs, &slots, &mut walk) {
+                            sent += 1;
+                        }
+                        true
+                    })?;
+                    walk.listing = listing;
+                    Ok(walk)
+                })
+                .collect()
+        };
+        let walks = panic::catch_unwind(AssertUnwindSafe(walk_all))
+            .unwrap_or(Err(ConfirmerError::ThreadPanic));
+        if walks.is_err() {
+            jobs.cancelled.store(true, Ordering::Relaxed);
+        }
+        jobs.sender.send(HashMessage::WalkEnded { sent }).expect("Could not send end of walk");
+        walks
+    }
+
+    /// Count file found in a destination and send it to the workers if it can be a copy of a
+    /// file in source
+    ///
+    /// Returns true if the file is sent, as a job or as [HashMessage::Link].
+    ///
+    /// # Arguments
+    /// * `file` - file found in a destination
+    /// * `jobs` - creates the jobs
+    /// * `slots` - bounded channel of the jobs and hardlinks not handled yet
+    /// * `walk` - walk of the destination
+    fn _add_file(
+        &self,
+        mut file: FileEntry,
+        jobs: &HashJobs,
+        slots: &SyncSender<()>,
+        walk: &mut DestinationWalk,
+    ) -> bool {
+        walk.files += 1;
+        walk.bytes += file.size.unwrap_or(0);
+        let key = file.inode_key().filter(|_| file.links > 1);
+        if let Some(key) = key {
+            walk.links.push((key, file.path.clone()));
+        }
+        // Files of unknown size from manifests can match a file of any size
+        let sizes = &self.source_sizes;
+        if file.size.is_some() && !sizes.contains(&None) && !sizes.contains(&file.size) {
+            return false;
+        }
+
+        // Hardlinks of files in source get their checksums, further hardlinks of a file sent to
+        // the workers are sent to the reader of the channel to get the checksum of the file
+        let mut is_link = false;
+        if let (Some(key), None) = (key, &file.hash) {
+            file.hash = self.source_inodes.get(&key).cloned();
+            let mut sent_inodes = self.sent_inodes.lock().expect("Lock is poisoned");
+            is_link = file.hash.is_none() && !sent_inodes.insert(key);
+        }
+        slots.send(()).expect("Slots of jobs are closed");
+        if is_link {
+            jobs.sender.send(HashMessage::Link(file)).expect("Could not send hardlink");
+        } else {
+            jobs.observers.hash_queued(&file.path, jobs.bytes_to_read(&file, HashStage::Full));
+            self.workers.execute(jobs.job(file, HashStage::Full));
+        }
+        true
+    }
+}
+
+/// Files of a comparison sorted out so far
+#[derive(Default)]
+struct Matching {
+    /// Keys = hashes of files in source that were not yet found, values = files with the hash
+    missing: HashMap<String, Vec<FileEntry>>,
+    /// Files in source ruled out before calculating checksum of the whole file
+    unmatched: Vec<FileEntry>,
+    /// Hash map for the result, files in source found in destinations
+    found: HashMap<String, FileFound>,
+    /// Keys = hashes of found files, values = size of the files
+    found_sizes: HashMap<String, Option<u64>>,
+    /// Keys = hashes of found files, values = relative paths of the files in source in the order
+    /// of their paths in `found`. Collected only in strict mode.
+    found_paths: HashMap<String, Vec<PathBuf>>,
+    /// Hashes of found files with fewer copies than required
+    under_replicated: HashSet<String>,
+    /// Keys = hashes of missing files, values = index of the destination and path of files with
+    /// the hash at other paths
+    moved: HashMap<String, Vec<(usize, OsString)>>,
+    /// Keys = hashes of files in source, values = their relative paths. Collected only if extra
+    /// files are requested.
+    source_paths: HashMap<String, Vec<PathBuf>>,
+    /// Index of the destination and path of files without a counterpart in source
+    extra: Vec<(usize, OsString)>,
+}
 
-/// Time period for checking the threadpool status
-const HUNDRED_MILIS: time::Duration = time::Duration::from_millis(100);
+/// Default number of bytes checksummed at each end of a file in the partial stage
+const DEFAULT_PARTIAL_BLOCK_SIZE: u64 = 64 * 1024;
+
+/// Default minimal size of a file to go through the partial stage
+const DEFAULT_PARTIAL_MIN_SIZE: u64 = 1024 * 1024;
 
 /// Structure providing methods for directory comparison
 pub struct CopyConfirmer {
-    hashes_tx: Sender<HashResult>,
-    hashes_rx: Receiver<HashResult>,
-    threadpool: ThreadPool,
-    show_progress: bool,
+    hashes_tx: Sender<HashMessage>,
+    hashes_rx: Receiver<HashMessage>,
+    workers: Workers,
+    all_copies: bool,
+    strict_paths: bool,
+    extra_files: bool,
+    min_copies: usize,
+    error_policy: ErrorPolicy,
+    symlink_policy: SymlinkPolicy,
+    hash_algorithm: HashAlgorithm,
+    hash_cache: Option<Arc<HashCache>>,
+    partial_block_size: u64,
+    partial_min_size: u64,
     excluded_pattern: Vec<ExcludePattern>,
+    excluded_dest_pattern: Vec<ExcludePattern>,
+    include_filter: Vec<IncludeFilter>,
+    ignore_files: bool,
+    parallel_walk: bool,
     excluded_paths: Cell<Vec<OsString>>,
+    hashed_files: Arc<AtomicU64>,
+    progress: Progress,
+    observers: Observers,
 }
 
 impl CopyConfirmer {
@@ -133,27 +759,164 @@ impl CopyConfirmer {
     /// * `num_threads` - number of jobs for checksum calculation to be run in parallel
     pub fn new(num_threads: usize) -> Self {
         let (hashes_tx, hashes_rx) = channel();
-        let threadpool = ThreadPool::new(num_threads);
+        let workers = Workers::new(num_threads);
+        let progress = Progress::with_workers(num_threads);
+        let observers = Observers(vec![Arc::new(progress.clone())]);
         Self {
             hashes_tx,
             hashes_rx,
-            threadpool,
-            show_progress: false,
+            workers,
+            all_copies: false,
+            strict_paths: false,
+            extra_files: false,
+            min_copies: 1,
+            error_policy: ErrorPolicy::default(),
+            symlink_policy: SymlinkPolicy::default(),
+            hash_algorithm: HashAlgorithm::default(),
+            hash_cache: None,
+            partial_block_size: DEFAULT_PARTIAL_BLOCK_SIZE,
+            partial_min_size: DEFAULT_PARTIAL_MIN_SIZE,
             excluded_pattern: vec![],
+            excluded_dest_pattern: vec![],
+            include_filter: vec![],
+            ignore_files: false,
+            parallel_walk: false,
             excluded_paths: Cell::new(vec![]),
+            hashed_files: Arc::new(AtomicU64::new(0)),
+            progress,
+            observers,
         }
     }
 
     /// Enable progress bar
+    ///
+    /// The bar shows bytes hashed, throughput and estimated time left of each phase, see
+    /// [ProgressBarObserver].
     pub fn with_progress_bar(self) -> Self {
-        Self {
-            hashes_tx: self.hashes_tx,
-            hashes_rx: self.hashes_rx,
-            threadpool: self.threadpool,
-            show_progress: true,
-            excluded_pattern: self.excluded_pattern,
-            excluded_paths: self.excluded_paths,
-        }
+        self.add_observer(Arc::new(ProgressBarObserver::new()))
+    }
+
+    /// Register observer notified of events of comparisons
+    ///
+    /// # Arguments
+    /// * `observer` - observer to be notified, see [Observer]
+    pub fn add_observer(self, observer: Arc<dyn Observer>) -> Self {
+        let mut modifiable = self;
+        modifiable.observers.0.push(observer);
+        modifiable
+    }
+
+    /// Get handle to the progress of comparisons
+    ///
+    /// The handle can be sent to another thread and polled with
+    /// [snapshot](Progress::snapshot) while a comparison runs.
+    pub fn progress(&self) -> Progress {
+        self.progress.clone()
+    }
+
+    /// Look for all copies of source files in destinations
+    ///
+    /// By default, destinations are walked and hashed only until every file in source is found.
+    /// With this option all files in destinations get walked and hashed, so that
+    /// [FileFound](FileFound) contains every copy of each file.
+    pub fn with_all_copies(self) -> Self {
+        let mut modifiable = self;
+        modifiable.all_copies = true;
+        modifiable
+    }
+
+    /// Require each file in source to be at the same relative path in one of destinations
+    ///
+    /// By default, a file in source is found if a file with the same content is anywhere in
+    /// destinations. In strict mode, files whose content is only at a different relative path are
+    /// reported as [moved](ConfirmerResult::moved).
+    pub fn with_strict_paths(self) -> Self {
+        let mut modifiable = self;
+        modifiable.strict_paths = true;
+        modifiable
+    }
+
+    /// Require each file in source to be in at least `min_copies` destinations
+    ///
+    /// Files found in fewer destinations are reported as
+    /// [under_replicated](ConfirmerResult::under_replicated). Copies within a single destination
+    /// are counted once.
+    ///
+    /// Defaults to 1.
+    pub fn with_min_copies(self, min_copies: usize) -> Self {
+        let mut modifiable = self;
+        modifiable.min_copies = min_copies;
+        modifiable
+    }
+
+    /// Look for files in destinations without a counterpart in source
+    ///
+    /// All files in destinations get hashed, so that [extra](ConfirmerResult::extra) lists every
+    /// file whose content is not in source. With
+    /// [with_strict_paths](CopyConfirmer::with_strict_paths), files whose content is in source
+    /// only at a different relative path are extra as well.
+    pub fn with_extra_files(self) -> Self {
+        let mut modifiable = self;
+        modifiable.extra_files = true;
+        modifiable
+    }
+
+    /// Set what to do with files that cannot be read
+    ///
+    /// Defaults to [ErrorPolicy::Abort].
+    pub fn with_error_policy(self, policy: ErrorPolicy) -> Self {
+        let mut modifiable = self;
+        modifiable.error_policy = policy;
+        modifiable
+    }
+
+    /// Set what to do with symbolic links in source and destinations
+    ///
+    /// Defaults to [SymlinkPolicy::Skip].
+    pub fn with_symlink_policy(self, policy: SymlinkPolicy) -> Self {
+        let mut modifiable = self;
+        modifiable.symlink_policy = policy;
+        modifiable
+    }
+
+    /// Set hash algorithm used to compare files
+    ///
+    /// Use [HashAlgorithm::Custom] to plug in an algorithm implementing [FileHasher]. Defaults to
+    /// [HashAlgorithm::Blake2b].
+    pub fn with_hash_algorithm(self, algorithm: HashAlgorithm) -> Self {
+        let mut modifiable = self;
+        modifiable.hash_algorithm = algorithm;
+        modifiable
+    }
+
+    /// Reuse checksums of unchanged files from a persistent cache
+    ///
+    /// Checksums of the whole files are looked up in the cache before they get calculated and
+    /// new checksums are stored in it. Checksums of files deleted from the compared directories
+    /// are removed. The cache is saved after each comparison.
+    pub fn with_hash_cache(self, cache: HashCache) -> Self {
+        let mut modifiable = self;
+        modifiable.hash_cache = Some(Arc::new(cache));
+        modifiable
+    }
+
+    /// Set thresholds for the partial checksum stage
+    ///
+    /// Files with at least `min_file_size` bytes are first compared by checksum of their first and
+    /// last `block_size` bytes and the whole file is hashed only if this checksum matches a file
+    /// on the other side. Smaller files are hashed whole right away. Use `u64::MAX` as
+    /// `min_file_size` to disable the partial stage.
+    ///
+    /// Defaults to 64 KiB blocks for files of at least 1 MiB.
+    ///
+    /// # Arguments
+    /// * `block_size` - number of bytes checksummed at each end of a file
+    /// * `min_file_size` - minimal size of a file to go through the partial stage
+    pub fn with_partial_hash(self, block_size: u64, min_file_size: u64) -> Self {
+        let mut modifiable = self;
+        modifiable.partial_block_size = block_size;
+        modifiable.partial_min_size = min_file_size;
+        modifiable
     }
 
     /// Add exclude pattern
@@ -171,10 +934,69 @@ impl CopyConfirmer {
         modifiable
     }
 
+    /// Add exclude pattern for destinations
+    ///
+    /// Files in destinations matching the pattern are neither hashed nor matched with files in
+    /// source, e.g. files in trash or snapshot directories. Directories matching the pattern are
+    /// not walked at all. The excluded files are not reported.
+    ///
+    /// [ExcludePattern::MatchPathStart] is matched against the whole path including the
+    /// destination root, other patterns are relative to the root of each destination.
+    ///
+    /// The method can be used multiple times to exclude multiple patterns.
+    pub fn add_excluded_dest_pattern(self, exclude: ExcludePattern) -> Self {
+        let mut modifiable = self;
+        modifiable.excluded_dest_pattern.push(exclude);
+        modifiable
+    }
+
+    /// Add include filter
+    ///
+    /// Only files in source passing all include filters are compared, the other files are
+    /// excluded as if they matched an exclude pattern.
+    ///
+    /// The method can be used multiple times to add multiple filters.
+    pub fn add_include_filter(self, filter: IncludeFilter) -> Self {
+        let mut modifiable = self;
+        modifiable.include_filter.push(filter);
+        modifiable
+    }
+
+    /// Exclude files in source ignored by `.gitignore`, `.ignore` or `.copconignore` files
+    ///
+    /// The ignore files are looked up in every directory of the source tree and their patterns
+    /// apply to the directory and its subdirectories, as in git. Ignored directories are not
+    /// walked at all. Manifests used as source are not affected.
+    pub fn with_ignore_files(self) -> Self {
+        let mut modifiable = self;
+        modifiable.ignore_files = true;
+        modifiable
+    }
+
+    /// Walk source and all destinations in parallel, each in its own thread
+    ///
+    /// Speeds up the comparison of locations on different disks or network mounts. Destinations
+    /// walked while their files get hashed, see [compare](CopyConfirmer::compare), are walked in
+    /// parallel once source is hashed.
+    pub fn with_parallel_walk(self) -> Self {
+        let mut modifiable = self;
+        modifiable.parallel_walk = true;
+        modifiable
+    }
+
     /// Check if all files in source are also in one of destinations
     ///
-    /// Returns `ConfirmerResult::Ok` if all files in `source` directory are in at least one
-    /// directory in `destinations`. Returns `ConfirmerResult::MissingFiles()`
+    /// Returns [ConfirmerResult] with the files found in `destinations` and the files of `source`
+    /// missing from all of them. The copy is confirmed if
+    /// [is_complete](ConfirmerResult::is_complete) returns true.
+    ///
+    /// Destinations are walked while their files get hashed and both stop once all files in source
+    /// are found, so the [FileFound](FileFound) entries need not list every copy and destinations
+    /// need not be walked in full. Sizes of files in destinations are not known when source gets
+    /// hashed, so all files in source are hashed. Use
+    /// [with_all_copies](CopyConfirmer::with_all_copies) to walk and hash all files in
+    /// destinations, then files in source without a destination file of the same size are not
+    /// hashed.
     ///
     /// # Arguments
     /// * `source` - path to the source directory
@@ -184,249 +1006,1221 @@ impl CopyConfirmer {
         source: T,
         destinations: &[T],
     ) -> Result<ConfirmerResult, ConfirmerError> {
-        // Total numbers of files for progress bars
-        let source: &OsStr = source.as_ref();
-        let mut excluded_files: Vec<OsString> = vec![];
-        let destinations: Vec<&OsStr> = destinations.iter().map(|x| x.as_ref()).collect();
-        let total_files_source = get_total_files(source);
-        let total_dest_files: u64 = destinations.iter().map(|x| get_total_files(x)).sum();
-
-        // Keys = hashes of files in source dir, values = vectors of paths to files with the hash
-        let mut missing_files: HashMap<String, Vec<OsString>> = HashMap::new();
-        // hash map for Ok result
-        let mut found_files: HashMap<String, FileFound> = HashMap::new();
-
-        self._enqueue_all_hashes_src(source, &mut excluded_files)?;
+        let source = Location::Dir(source.as_ref().into());
+        let destinations: Vec<Location> =
+            destinations.iter().map(|x| Location::Dir(x.as_ref().into())).collect();
+        self.compare_locations(&source, &destinations)
+    }
 
-        // To reduce total files count in progress
-        let excluded_count = excluded_files.len();
-        // Add excluded files to self, so that it can be exported
-        let mut ex_paths = self.excluded_paths.take();
-        ex_paths.append(&mut excluded_files);
-        self.excluded_paths.set(ex_paths);
+    /// Check if all files in source are also in one of destinations, any of which can be a
+    /// [Manifest]
+    ///
+    /// Works the same way as [compare](CopyConfirmer::compare), but files listed in a manifest
+    /// are compared by the checksums stored in it. The manifests must use the same hash
+    /// algorithm as `CopyConfirmer`.
+    ///
+    /// # Arguments
+    /// * `source` - source directory or manifest
+    /// * `destinations` - destination directories or manifests
+    pub fn compare_locations(
+        &self,
+        source: &Location,
+        destinations: &[Location],
+    ) -> Result<ConfirmerResult, ConfirmerError> {
+        let result = self._compare(source, destinations);
+        self.observers.phase_changed(Phase::Idle, 0, 0);
 
-        self._track_progress(
-            total_files_source - excluded_count as u64,
-            "Checking files from source",
-        );
+        // Keep the checksums calculated so far even if the comparison failed
+        if let Some(cache) = &self.hash_cache {
+            cache.save()?;
+        }
+        result
+    }
 
-        // Return Error on any panic
-        if self.threadpool.panic_count() > 0 {
-            return Err(ConfirmerError("A panic occured while calculating hashes.".into()));
+    /// Compare directories, see [compare](CopyConfirmer::compare)
+    fn _compare(
+        &self,
+        source: &Location,
+        destinations: &[Location],
+    ) -> Result<ConfirmerResult, ConfirmerError> {
+        let start = Instant::now();
+        self.hashed_files.store(0, Ordering::Relaxed);
+        let mut excluded_files: Vec<OsString> = vec![];
+        let mut unreadable_files: Vec<UnreadableFile> = vec![];
+        for location in std::iter::once(source).chain(destinations) {
+            self._check_manifest_algorithm(location)?;
         }
-        // Add hashes for all files found in source dir to `missing files`
-        for result in self.hashes_rx.try_iter() {
-            match result {
-                (path, Ok(hash)) => {
-                    // FIXME: do this without cloning
-                    // Append if there is already an entry with the same hash
-                    missing_files
-                        .entry(hash)
-                        .and_modify(|vec| vec.push(path.clone()))
-                        .or_insert(vec![path]);
-                }
-                (path, Err(e)) => {
-                    eprintln!("Error getting hash {:?}: {}", path, e);
-                    return Err(e.into());
-                }
-            }
+        if self.min_copies > 1 && self.min_copies > destinations.len() {
+            return Err(ConfirmerError::InvalidSettings(format!(
+                "{} copies of each file are required, but only {} destinations are given",
+                self.min_copies,
+                destinations.len()
+            )));
         }
 
-        // Get hashes for all files in destinations
+        // Destinations are walked while their files get hashed if the comparison can stop once
+        // all files are found. Otherwise all directories are walked first, so that only files with
+        // the size of some file on the other side get hashed.
+        let streamed = !self.all_copies && !self.extra_files;
+        let mut broken_links = BrokenLinks::default();
+        let dest_matcher =
+            ExcludeMatcher::new(&self.excluded_dest_pattern)?.with_relative_substrings();
+        self.observers.phase_changed(Phase::Walk, 0, 0);
+        let mut locations = vec![(source, self._walk_settings(true, None))];
         for dest in destinations {
-            self._enqueue_all_hashes(dest)?;
+            locations.push((dest, self._walk_settings(false, Some(&dest_matcher))));
         }
-
-        // FIXME: Would be better to use the results continually instead of waiting for all hashes
-        // and return early once missing_files is empty, since destinations dirs can be
-        // significantly larger than source dir
-        self._track_progress(total_dest_files, "Checking files from destinations");
-
-        // Return Error on any panic
-        if self.threadpool.panic_count() > 0 {
-            return Err(ConfirmerError("A panic occured while calculating hashes.".into()));
+        let listed = match streamed {
+            true => &locations[..1],
+            false => &locations[..],
+        };
+        let listings = list_locations(listed, self.parallel_walk)?;
+        if let Some(cache) = &self.hash_cache {
+            for ((location, _), listing) in listed.iter().zip(&listings) {
+                if let Location::Dir(root) = location {
+                    cache.prune(root, listing.files.iter().map(|f| f.path.as_os_str()));
+                }
+            }
         }
+        let mut listings = listings.into_iter();
 
-        // Remove all files found in destinations from `missing_files`
-        for result in self.hashes_rx.try_iter() {
-            match result {
-                (dest_path, Ok(hash)) => {
-                    if let Some(src_paths) = missing_files.remove(&hash) {
-                        found_files
-                            .entry(hash)
-                            .and_modify(|FileFound { dest_paths, .. }| {
-                                dest_paths.push(dest_path.clone())
-                            })
-                            .or_insert(FileFound { src_paths, dest_paths: vec![dest_path] });
+        let source_listing = listings.next().expect("Source is always listed");
+        let source_files = source_listing.into_files(
+            &mut excluded_files,
+            &mut broken_links,
+            &mut unreadable_files,
+        );
+        let source_files = self._filter_source(source_files, &mut excluded_files)?;
+        let mut links: Vec<((u64, u64), OsString)> =
+            source_files.iter().filter_map(|f| Some((f.inode_key()?, f.path.clone()))).collect();
+        let mut stats = ComparisonStats {
+            source_files: source_files.len() as u64,
+            source_bytes: source_files.iter().filter_map(|f| f.size).sum(),
+            ..Default::default()
+        };
+
+        let mut matching = Matching::default();
+        if streamed {
+            let walks = self._compare_streamed(
+                source_files,
+                &locations[1..],
+                &mut matching,
+                &mut unreadable_files,
+            )?;
+            for walk in walks {
+                stats.dest_files += walk.files;
+                stats.dest_bytes += walk.bytes;
+                links.extend(walk.links);
+                walk.listing.into_files(
+                    &mut excluded_files,
+                    &mut broken_links,
+                    &mut unreadable_files,
+                );
+            }
+            // Destinations need not be walked in full, so cached files in them are checked on disk
+            if let Some(cache) = &self.hash_cache {
+                for dest in destinations {
+                    if let Location::Dir(root) = dest {
+                        cache.prune(root, std::iter::empty());
                     }
                 }
-                (dest_path, Err(e)) => {
-                    eprintln!("Error getting hash {:?}: {}", dest_path, e);
-                    return Err(e.into());
-                }
             }
-        }
-
-        // Return all files left in `missing_files` or `Ok`
-        if missing_files.is_empty() {
-            Ok(ConfirmerResult::Ok(found_files))
         } else {
-            Ok(ConfirmerResult::MissingFiles(missing_files.into_values().flatten().collect()))
+            let mut dest_files: Vec<FileEntry> = vec![];
+            for (index, listing) in listings.enumerate() {
+                let mut files = listing.into_files(
+                    &mut excluded_files,
+                    &mut broken_links,
+                    &mut unreadable_files,
+                );
+                files.iter_mut().for_each(|f| f.location = index);
+                dest_files.append(&mut files);
+            }
+            stats.dest_files = dest_files.len() as u64;
+            stats.dest_bytes = dest_files.iter().filter_map(|f| f.size).sum();
+            links.extend(dest_files.iter().filter_map(|f| Some((f.inode_key()?, f.path.clone()))));
+            self._compare_listed(source_files, dest_files, &mut matching, &mut unreadable_files)?;
         }
-    }
+        let hardlinks = hardlink_groups(links);
 
-    /// Get paths of files that were excluded from comparison
-    ///
-    /// See [add_excluded_pattern](CopyConfirmer::add_excluded_pattern) and [ExcludePattern].
-    pub fn get_excluded_paths(&self) -> Vec<OsString> {
+        // Add excluded files to self, so that it can be exported
+        let mut ex_paths = self.excluded_paths.take();
+        ex_paths.extend(excluded_files.iter().cloned());
+        self.excluded_paths.set(ex_paths);
+
+        // Files left in `missing` have no copy at the same path, they are moved if there is a copy
+        // elsewhere
+        let Matching {
+            mut missing,
+            unmatched,
+            found,
+            found_sizes,
+            under_replicated,
+            moved: mut moved_files,
+            extra,
+            ..
+        } = matching;
+        let mut moved: HashMap<String, FileFound> = HashMap::new();
+        missing.retain(|hash, sources| match moved_files.remove(hash) {
+            Some(dests) => {
+                let src_paths = sources.drain(..).map(|f| f.path).collect();
+                let (dest_locations, dest_paths) = dests.into_iter().unzip();
+                let file_found =
+                    FileFound { src_paths, dest_paths, dest_locations, dest_sources: vec![] };
+                moved.insert(hash.clone(), file_found);
+                false
+            }
+            None => true,
+        });
+        let mut missing: Vec<OsString> =
+            missing.into_values().flatten().chain(unmatched).map(|f| f.path).collect();
+        missing.sort();
+        missing.iter().for_each(|path| self.observers.file_missing(path));
+        let under_replicated: HashMap<String, FileFound> = under_replicated
+            .into_iter()
+            .map(|hash| {
+                let file_found = found[&hash].clone();
+                (hash, file_found)
+            })
+            .collect();
+        let mut coverage = vec![DestinationCoverage::default(); destinations.len()];
+        for (hash, file_found) in &found {
+            let size = found_sizes.get(hash).copied().flatten().unwrap_or_default();
+            for location in file_found.copy_locations().into_iter().flatten() {
+                coverage[location].files += 1;
+                coverage[location].bytes += size;
+            }
+        }
+        let mut extra_files: Vec<Vec<OsString>> = vec![];
+        if self.extra_files {
+            extra_files.resize(destinations.len(), vec![]);
+            for (location, path) in extra {
+                extra_files[location].push(path);
+            }
+            extra_files.iter_mut().for_each(|paths| paths.sort());
+        }
+        unreadable_files.sort_by(|a, b| a.path.cmp(&b.path));
+        broken_links.dangling.sort();
+        broken_links.loops.sort();
+        stats.hashed_files = self.hashed_files.swap(0, Ordering::Relaxed);
+        stats.elapsed = start.elapsed();
+        Ok(ConfirmerResult {
+            found,
+            missing,
+            moved,
+            under_replicated,
+            coverage,
+            extra: extra_files,
+            excluded: excluded_files,
+            unreadable: unreadable_files,
+            dangling_links: broken_links.dangling,
+            symlink_loops: broken_links.loops,
+            hardlinks,
+            stats,
+        })
+    }
+
+    /// Get paths of files that were excluded from comparison
+    ///
+    /// See [add_excluded_pattern](CopyConfirmer::add_excluded_pattern) and [ExcludePattern].
+    pub fn get_excluded_paths(&self) -> Vec<OsString> {
         let ex_paths = self.excluded_paths.take();
         let result = ex_paths.clone();
         self.excluded_paths.set(ex_paths);
         result
     }
 
-    /// Go recursively through directory. For each file add a job to calculate its checksum to the
-    /// threadpool.
+    /// Match files in source with files in destinations listed in full
+    ///
+    /// Files in source without a destination file of the same size are missing without hashing
+    /// and vice versa, large files of the same size are ruled out by checksums of their ends.
+    ///
+    /// # Arguments
+    /// * `source_files` - all files in source
+    /// * `dest_files` - all files in destinations
+    /// * `matching` - files of the comparison sorted out so far
+    /// * `unreadable_files` - files skipped because of an error
+    fn _compare_listed(
+        &self,
+        source_files: Vec<FileEntry>,
+        dest_files: Vec<FileEntry>,
+        matching: &mut Matching,
+        unreadable_files: &mut Vec<UnreadableFile>,
+    ) -> Result<(), ConfirmerError> {
+        // Files of unknown size from manifests can match a file of any size
+        let source_sizes: HashSet<Option<u64>> = source_files.iter().map(|f| f.size).collect();
+        let dest_sizes: HashSet<Option<u64>> = dest_files.iter().map(|f| f.size).collect();
+        let size_matches = |sizes: &HashSet<Option<u64>>, f: &FileEntry| {
+            f.size.is_none() || sizes.contains(&None) || sizes.contains(&f.size)
+        };
+        let (source_files, unmatched): (Vec<FileEntry>, Vec<FileEntry>) =
+            source_files.into_iter().partition(|f| size_matches(&dest_sizes, f));
+        let (dest_files, extra): (Vec<FileEntry>, Vec<FileEntry>) =
+            dest_files.into_iter().partition(|f| size_matches(&source_sizes, f));
+        matching.unmatched = unmatched;
+        if self.extra_files {
+            matching.extra.extend(extra.into_iter().map(|f| (f.location, f.path)));
+        }
+        debug!(
+            "Size prefilter: {} source files and {} destination files left to hash",
+            source_files.len(),
+            dest_files.len()
+        );
+
+        // Rule out large files of the same size by checksums of their beginnings and ends
+        let (source_files, dest_files) =
+            self._filter_by_partial_hash(source_files, dest_files, matching, unreadable_files)?;
+
+        // Add hashes for all files found in source dir to `missing`
+        let source_hashes =
+            self._collect_hashes(source_files, HashStage::Full, Phase::Source, unreadable_files)?;
+        // Destination files that are hardlinks of source files get their checksums
+        let (dest_linked, mut dest_files) = reuse_inode_hashes(&source_hashes, dest_files);
+        dest_files.extend(
+            dest_linked.into_iter().map(|(file, hash)| FileEntry { hash: Some(hash), ..file }),
+        );
+        for (file, hash) in source_hashes {
+            if self.extra_files {
+                matching
+                    .source_paths
+                    .entry(hash.clone())
+                    .or_default()
+                    .push(file.relative_path.clone());
+            }
+            matching.missing.entry(hash).or_default().push(file);
+        }
+
+        // Match the hashes of destination files as they arrive. Once all files are found, the
+        // remaining jobs skip the hash calculation.
+        self._match_destination_hashes(dest_files, matching, unreadable_files)
+    }
+
+    /// Match files in source with files in destinations walked while they are hashed
     ///
-    /// Returns std::io::Error if any path cannot be accessed
+    /// Sizes of files in destinations are not known before they are walked, so all files in
+    /// source get hashed. Destinations are not walked at all if no file in source is left to
+    /// find.
+    ///
+    /// Returns the walks of the destinations, see
+    /// [_match_streamed_destinations](CopyConfirmer::_match_streamed_destinations).
     ///
     /// # Arguments
-    /// * `dir` - directory to go through and get all hashes
-    fn _enqueue_all_hashes(&self, dir: &OsStr) -> IoResult<()> {
-        for item in WalkDir::new(dir) {
-            let item = item?;
-            if !item.file_type().is_file() {
-                continue;
-            }
-            let path = item.into_path().into_os_string();
-            let sender = self.hashes_tx.clone();
-            self.threadpool.execute(move || {
-                sender
-                    .send((path.clone(), get_hash(path)))
-                    .expect("Could not send source file hash")
+    /// * `source_files` - all files in source
+    /// * `destinations` - destinations with the settings of their walks
+    /// * `matching` - files of the comparison sorted out so far
+    /// * `unreadable_files` - files skipped because of an error
+    fn _compare_streamed(
+        &self,
+        source_files: Vec<FileEntry>,
+        destinations: &[(&Location, WalkSettings)],
+        matching: &mut Matching,
+        unreadable_files: &mut Vec<UnreadableFile>,
+    ) -> Result<Vec<DestinationWalk>, ConfirmerError> {
+        let (source_files, source_ends) = self._hash_source_ends(source_files, unreadable_files)?;
+        let source_hashes =
+            self._collect_hashes(source_files, HashStage::Full, Phase::Source, unreadable_files)?;
+        let source_inodes: HashMap<(u64, u64), String> = source_hashes
+            .iter()
+            .filter(|(file, _)| file.links > 1)
+            .filter_map(|(file, hash)| Some((file.inode_key()?, hash.clone())))
+            .collect();
+        for (file, hash) in source_hashes {
+            matching.missing.entry(hash).or_default().push(file);
+        }
+        if !self._needs_more(matching) {
+            return Ok(vec![]);
+        }
+        self._match_streamed_destinations(
+            destinations,
+            source_ends,
+            source_inodes,
+            matching,
+            unreadable_files,
+        )
+    }
+
+    /// Calculate checksums of ends of large files in source, see [SourceEnds]
+    ///
+    /// Returns the files in source with the checksums of the ends, none if no file in source
+    /// gets them. Files that could not be read are left out.
+    ///
+    /// # Arguments
+    /// * `source_files` - all files in source
+    /// * `unreadable_files` - files skipped because of an error
+    fn _hash_source_ends(
+        &self,
+        source_files: Vec<FileEntry>,
+        unreadable_files: &mut Vec<UnreadableFile>,
+    ) -> Result<(Vec<FileEntry>, Option<SourceEnds>), ConfirmerError> {
+        // The blocks would cover the whole file, so the partial stage would not save anything
+        let min_size = max(self.partial_min_size, 2 * self.partial_block_size + 1);
+        // Files from manifests have no partial checksum, so files of their size are hashed whole
+        let known_sizes: HashSet<Option<u64>> =
+            source_files.iter().filter(|f| f.hash.is_some()).map(|f| f.size).collect();
+        if known_sizes.contains(&None) {
+            return Ok((source_files, None));
+        }
+        let (large, mut source_files): (Vec<FileEntry>, Vec<FileEntry>) =
+            source_files.into_iter().partition(|f| {
+                f.size.map_or(false, |size| size >= min_size) && !known_sizes.contains(&f.size)
             });
+        if large.is_empty() {
+            return Ok((source_files, None));
         }
-        Ok(())
+
+        let block_size = self.partial_block_size;
+        let stage = HashStage::Partial { block_size };
+        let hashes = self._collect_hashes(large, stage, Phase::FileEnds, unreadable_files)?;
+        let mut ends = SourceEnds { block_size, sizes: HashSet::new(), hashes: HashSet::new() };
+        for (file, hash) in hashes {
+            let size = file.size.expect("Large files have known size");
+            ends.sizes.insert(size);
+            ends.hashes.insert((size, hash));
+            source_files.push(file);
+        }
+        Ok((source_files, Some(ends)))
+    }
+
+    /// Walk destinations and match their files with files in source as their checksums arrive
+    ///
+    /// Each destination is walked in its own thread with
+    /// [with_parallel_walk](CopyConfirmer::with_parallel_walk), all of them one by one in a
+    /// single thread otherwise. The walks add jobs to the workers as they find files of the size
+    /// of some file in source. They wait while [capacity](Workers::capacity) jobs and hardlinks
+    /// are queued, running or with results not handled yet, so memory does not grow with the
+    /// number of files in destinations. Once each file in source has enough copies, both the
+    /// walks and the hashing stop.
+    ///
+    /// Returns the walks of the destinations.
+    ///
+    /// # Arguments
+    /// * `destinations` - destinations with the settings of their walks
+    /// * `source_ends` - checksums of ends of large files in source
+    /// * `source_inodes` - checksums of files in source with several hardlinks by their device
+    ///   and inode
+    /// * `matching` - files of the comparison sorted out so far
+    /// * `unreadable_files` - files skipped because of an error
+    fn _match_streamed_destinations(
+        &self,
+        destinations: &[(&Location, WalkSettings)],
+        source_ends: Option<SourceEnds>,
+        source_inodes: HashMap<(u64, u64), String>,
+        matching: &mut Matching,
+        unreadable_files: &mut Vec<UnreadableFile>,
+    ) -> Result<Vec<DestinationWalk>, ConfirmerError> {
+        self.observers.phase_changed(Phase::Destinations, 0, 0);
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let mut jobs = self._hash_jobs(&cancelled);
+        jobs.source_ends = source_ends.map(Arc::new);
+        let walker = DestinationWalker {
+            workers: &self.workers,
+            source_sizes: matching.missing.values().flatten().map(|f| f.size).collect(),
+            source_inodes,
+            sent_inodes: Mutex::new(HashSet::new()),
+        };
+        let destinations: Vec<(usize, &(&Location, WalkSettings))> =
+            destinations.iter().enumerate().collect();
+        let groups: Vec<&[(usize, &(&Location, WalkSettings))]> = match self.parallel_walk {
+            true => destinations.chunks(1).collect(),
+            false => vec![&destinations],
+        };
+        let (slots, taken_slots) = sync_channel::<()>(self.workers.capacity());
+
+        thread::scope(|scope| {
+            let handles: Vec<_> = groups
+                .iter()
+                .map(|group| {
+                    let (walker, jobs, slots) = (&walker, jobs.clone(), slots.clone());
+                    scope.spawn(move || walker.walk(group, jobs, slots))
+                })
+                .collect();
+
+            let mut walks_left = handles.len();
+            let (mut sent, mut received) = (0, 0);
+            // Checksums of files with several hardlinks and their hardlinks waiting for them
+            let mut link_hashes: HashMap<(u64, u64), Result<String, ConfirmerError>> =
+                HashMap::new();
+            let mut waiting_links: HashMap<(u64, u64), Vec<FileEntry>> = HashMap::new();
+            let mut outcome = Ok(());
+            let mut handle =
+                |result| self._match_destination_result(result, matching, unreadable_files);
+            while walks_left > 0 || received < sent {
+                let message = self.hashes_rx.recv().expect("Channel of file hashes is closed");
+                let mut results = vec![];
+                match message {
+                    HashMessage::WalkEnded { sent: count } => {
+                        walks_left -= 1;
+                        sent += count;
+                        continue;
+                    }
+                    HashMessage::Hashed(None) => {}
+                    HashMessage::Hashed(Some((file, hash))) => {
+                        if let Some(key) = file.inode_key().filter(|_| file.links > 1) {
+                            let link_hash = match &hash {
+                                Ok(hash) => Ok(hash.clone()),
+                                Err(e) => Err(e.with_path(PathBuf::from(&file.path))),
+                            };
+                            for link in waiting_links.remove(&key).unwrap_or_default() {
+                                results.push(link_result(link, &link_hash));
+                            }
+                            link_hashes.insert(key, link_hash);
+                        }
+                        results.insert(0, (file, hash));
+                    }
+                    HashMessage::Link(link) => {
+                        let key = link.inode_key().expect("Hardlinks have inode");
+                        match link_hashes.get(&key) {
+                            Some(link_hash) => results.push(link_result(link, link_hash)),
+                            None => waiting_links.entry(key).or_default().push(link),
+                        }
+                    }
+                }
+                received += 1;
+                for result in results {
+                    handle_hash_result(result, &cancelled, &mut handle, &mut outcome);
+                }
+                // The slot is freed once the message is handled, so that the walks stop before
+                // adding a job after a result that stops the hashing
+                taken_slots.recv().expect("Slots of jobs are closed");
+            }
+
+            let mut walks = vec![];
+            for walker in handles {
+                walks.extend(walker.join().map_err(|_| ConfirmerError::ThreadPanic)??);
+            }
+            outcome.map(|_| walks)
+        })
     }
 
-    /// Go recursively through directory. For each file add a job to calculate its checksum to the
-    /// threadpool. Does not process the directories/files that match excluded patterns given.
+    /// Remove files found in destinations from missing files as their hashes arrive
     ///
-    /// Returns std::io::Error if any path cannot be accessed
+    /// Returns once all files are hashed, or once each file has enough copies unless all copies
+    /// or extra files are requested.
     ///
     /// # Arguments
-    /// * `dir` - directory to go through and get all hashes
-    fn _enqueue_all_hashes_src(
+    /// * `dest_files` - files in destinations to calculate checksums of
+    /// * `matching` - files of the comparison sorted out so far
+    /// * `unreadable_files` - files skipped because of an error
+    fn _match_destination_hashes(
         &self,
-        dir: &OsStr,
+        dest_files: Vec<FileEntry>,
+        matching: &mut Matching,
+        unreadable_files: &mut Vec<UnreadableFile>,
+    ) -> Result<(), ConfirmerError> {
+        if !self._needs_more(matching) {
+            return Ok(());
+        }
+        self._run_hashes(dest_files, HashStage::Full, Phase::Destinations, |result| {
+            self._match_destination_result(result, matching, unreadable_files)
+        })
+    }
+
+    /// Returns true unless each file in source has enough copies and neither all copies nor
+    /// extra files are requested
+    fn _needs_more(&self, matching: &Matching) -> bool {
+        let all_found = matching.missing.is_empty() && matching.under_replicated.is_empty();
+        !all_found || self.all_copies || self.extra_files
+    }
+
+    /// Match a file in destinations and its hardlinks by its checksum, or handle its error
+    ///
+    /// Returns whether more files in destinations are needed, see
+    /// [_needs_more](CopyConfirmer::_needs_more).
+    ///
+    /// # Arguments
+    /// * `result` - file in destination with its checksum
+    /// * `matching` - files of the comparison sorted out so far
+    /// * `unreadable_files` - files skipped because of an error
+    fn _match_destination_result(
+        &self,
+        result: HashResult,
+        matching: &mut Matching,
+        unreadable_files: &mut Vec<UnreadableFile>,
+    ) -> Result<bool, ConfirmerError> {
+        match result {
+            (dest, Ok(hash)) => {
+                for link in dest.into_links() {
+                    self._match_destination_file(link, hash.clone(), matching);
+                }
+            }
+            (dest, Err(e)) => self._handle_link_errors(dest, e, unreadable_files)?,
+        }
+        Ok(self._needs_more(matching))
+    }
+
+    /// Move files in source with the same checksum as `dest` from missing to found files
+    ///
+    /// In strict mode only the files at the same relative path are found and `dest` is added to
+    /// found files only if it is at the relative path of a file in source. It is added to moved
+    /// files if other files in source with its checksum are left missing.
+    ///
+    /// # Arguments
+    /// * `dest` - file in destination
+    /// * `hash` - checksum of `dest`
+    /// * `matching` - files of the comparison sorted out so far
+    fn _match_destination_file(&self, dest: FileEntry, hash: String, matching: &mut Matching) {
+        if self.extra_files {
+            let has_source = matching
+                .source_paths
+                .get(&hash)
+                .map_or(false, |paths| !self.strict_paths || paths.contains(&dest.relative_path));
+            if !has_source {
+                matching.extra.push((dest.location, dest.path.clone()));
+            }
+        }
+
+        let mut src_files = vec![];
+        if let Some(sources) = matching.missing.get_mut(&hash) {
+            let (same_path, other_paths): (Vec<FileEntry>, Vec<FileEntry>) = sources
+                .drain(..)
+                .partition(|src| !self.strict_paths || src.relative_path == dest.relative_path);
+            if !same_path.is_empty() {
+                let size = same_path[0].size.or(dest.size);
+                matching.found_sizes.insert(hash.clone(), size);
+            }
+            for src in &same_path {
+                self.observers.file_matched(&src.path, &dest.path);
+            }
+            src_files = same_path;
+            *sources = other_paths;
+            if sources.is_empty() {
+                matching.missing.remove(&hash);
+                matching.moved.remove(&hash);
+            } else {
+                let moved = matching.moved.entry(hash.clone()).or_default();
+                moved.push((dest.location, dest.path.clone()));
+            }
+        }
+
+        // In strict mode `dest` is a copy only of a file in source at the same relative path
+        let is_copy = !self.strict_paths
+            || !src_files.is_empty()
+            || matching
+                .found_paths
+                .get(&hash)
+                .map_or(false, |paths| paths.contains(&dest.relative_path));
+        if !is_copy {
+            return;
+        }
+
+        let mut src_paths = vec![];
+        for src in src_files {
+            if self.strict_paths {
+                matching.found_paths.entry(hash.clone()).or_default().push(src.relative_path);
+            }
+            src_paths.push(src.path);
+        }
+        let dest_source = match self.strict_paths {
+            true => matching
+                .found_paths
+                .get(&hash)
+                .and_then(|paths| paths.iter().position(|path| path == &dest.relative_path)),
+            false => None,
+        };
+        let found = match matching.found.get_mut(&hash) {
+            Some(found) => found,
+            None if !src_paths.is_empty() => matching.found.entry(hash.clone()).or_default(),
+            None => return,
+        };
+        found.src_paths.append(&mut src_paths);
+        found.dest_paths.push(dest.path);
+        found.dest_locations.push(dest.location);
+        found.dest_sources.extend(dest_source);
+        // A newly found file in source may have fewer copies than the others
+        if self.min_copies > 1 && found.copies() < self.min_copies {
+            matching.under_replicated.insert(hash);
+        } else {
+            matching.under_replicated.remove(&hash);
+        }
+    }
+
+    /// Create manifest of a directory
+    ///
+    /// Files matching excluded patterns are left out of the manifest, as are files that cannot be
+    /// read with [ErrorPolicy::SkipAndReport]. Use
+    /// [Manifest::save] to store it and [compare_locations](CopyConfirmer::compare_locations)
+    /// to compare it.
+    ///
+    /// # Arguments
+    /// * `dir` - path to the directory
+    pub fn create_manifest<T: AsRef<OsStr>>(&self, dir: T) -> Result<Manifest, ConfirmerError> {
+        let root = PathBuf::from(dir.as_ref());
+        let result = self._create_manifest(root);
+        self.observers.phase_changed(Phase::Idle, 0, 0);
+
+        // Keep the checksums calculated so far even if the manifest could not be created
+        if let Some(cache) = &self.hash_cache {
+            cache.save()?;
+        }
+        result
+    }
+
+    /// Create manifest of a directory, see [create_manifest](CopyConfirmer::create_manifest)
+    fn _create_manifest(&self, root: PathBuf) -> Result<Manifest, ConfirmerError> {
+        let mut excluded_files: Vec<OsString> = vec![];
+        let mut unreadable_files: Vec<UnreadableFile> = vec![];
+        let mut broken_links = BrokenLinks::default();
+        self.observers.phase_changed(Phase::Walk, 0, 0);
+        let settings = self._walk_settings(true, None);
+        let listing = list_files(&Location::Dir(root.clone().into_os_string()), &settings)?;
+        let files =
+            listing.into_files(&mut excluded_files, &mut broken_links, &mut unreadable_files);
+        let files = self._filter_source(files, &mut excluded_files)?;
+
+        // Add excluded files to self, so that it can be exported
+        let mut ex_paths = self.excluded_paths.take();
+        ex_paths.append(&mut excluded_files);
+        self.excluded_paths.set(ex_paths);
+
+        let hashes =
+            self._collect_hashes(files, HashStage::Full, Phase::Source, &mut unreadable_files)?;
+        for file in unreadable_files {
+            eprintln!("Skipping unreadable file {:?}: {}", file.path, file.error);
+        }
+        if self.symlink_policy == SymlinkPolicy::Follow {
+            for link in broken_links.dangling {
+                eprintln!("Skipping dangling link {:?}", link);
+            }
+        }
+        for link in broken_links.loops {
+            eprintln!("Skipping symlink loop {:?}", link);
+        }
+        let mut entries: Vec<ManifestEntry> = hashes
+            .into_iter()
+            .map(|(file, hash)| ManifestEntry {
+                path: file.relative_path,
+                size: file.size,
+                mtime: file.mtime,
+                hash,
+            })
+            .collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(Manifest { root, algorithm: self.hash_algorithm, entries })
+    }
+
+    /// Leave out files in source matching excluded patterns or not passing include filters
+    ///
+    /// # Arguments
+    /// * `files` - all files in source
+    /// * `excluded_files` - vector to add the excluded files to
+    fn _filter_source(
+        &self,
+        files: Vec<FileEntry>,
         excluded_files: &mut Vec<OsString>,
-    ) -> IoResult<()> {
-        for item in WalkDir::new(dir) {
-            let item = item?;
-            if !item.file_type().is_file() {
-                continue;
-            }
-            let path = item.into_path().into_os_string();
-
-            // Filter out excluded patterns
-            if !self.excluded_pattern.is_empty() && is_path_excluded(&path, &self.excluded_pattern)
-            {
-                excluded_files.push(path);
-                continue;
-            }
-
-            let sender = self.hashes_tx.clone();
-            self.threadpool.execute(move || {
-                sender
-                    .send((path.clone(), get_hash(path)))
-                    .expect("Could not send source file hash")
-            });
+    ) -> Result<Vec<FileEntry>, ConfirmerError> {
+        let matcher = ExcludeMatcher::new(&self.excluded_pattern)?;
+        let mut files = files;
+        if !matcher.is_empty() {
+            let (excluded, included): (Vec<FileEntry>, Vec<FileEntry>) = files
+                .into_iter()
+                .partition(|file| matcher.is_excluded(&file.path, &file.relative_path));
+            excluded_files.extend(excluded.into_iter().map(|file| file.path));
+            files = included;
         }
-        Ok(())
+        if !self.include_filter.is_empty() {
+            let (included, excluded): (Vec<FileEntry>, Vec<FileEntry>) =
+                files.into_iter().partition(|file| {
+                    self.include_filter
+                        .iter()
+                        .all(|filter| filter.matches(&file.relative_path, file.size, file.mtime))
+                });
+            excluded_files.extend(excluded.into_iter().map(|file| file.path));
+            files = included;
+        }
+        Ok(files)
     }
 
-    /// Print progress bar that tracks progress on getting hashes of files
+    /// Settings of a walk of source or destination
     ///
     /// # Arguments
-    /// * `total_files` - number of files enqueued in the threadpool for calculation of hash
-    /// * `msg` - message to print with progress bar
-    fn _track_progress(&self, total_files: u64, msg: &'static str) {
-        let mut pbar: Option<ProgressBar> = None;
-        if self.show_progress {
-            let pb_style = ProgressStyle::with_template(
-                "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}",
-            )
-            .unwrap()
-            .progress_chars("##-");
-            pbar = Some(ProgressBar::new(total_files).with_style(pb_style));
-            pbar.as_ref().unwrap().set_message(msg);
+    /// * `is_source` - ignore files are honoured only in source
+    /// * `pruned` - files and directories to skip without reporting them
+    fn _walk_settings<'a>(
+        &'a self,
+        is_source: bool,
+        pruned: Option<&'a ExcludeMatcher<'a>>,
+    ) -> WalkSettings<'a> {
+        WalkSettings {
+            error_policy: self.error_policy,
+            symlink_policy: self.symlink_policy,
+            hash_algorithm: self.hash_algorithm,
+            ignore_files: is_source && self.ignore_files,
+            pruned,
+            observer: &self.observers,
         }
+    }
 
-        let mut num_not_done = self.threadpool.active_count() + self.threadpool.queued_count();
-        while num_not_done > 0 {
-            num_not_done = self.threadpool.active_count() + self.threadpool.queued_count();
-            if self.show_progress {
-                pbar.as_ref().unwrap().set_position(total_files - num_not_done as u64);
+    /// Return error if location is a manifest with checksums of different hash algorithm
+    fn _check_manifest_algorithm(&self, location: &Location) -> Result<(), ConfirmerError> {
+        match location {
+            Location::Manifest(manifest) if manifest.algorithm != self.hash_algorithm => {
+                Err(ConfirmerError::InvalidSettings(format!(
+                    "Manifest of {:?} uses hash algorithm {}, but {} is selected",
+                    manifest.root, manifest.algorithm, self.hash_algorithm
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Compare files of at least `partial_min_size` bytes by their partial checksums
+    ///
+    /// Files in source without a destination file with the same size and partial checksum are
+    /// added to unmatched files, such destination files are added to extra files if requested.
+    ///
+    /// Returns source files and destination files left for checksum of the whole file
+    ///
+    /// # Arguments
+    /// * `source_files` - files in source with a destination file of the same size
+    /// * `dest_files` - files in destinations with a source file of the same size
+    /// * `matching` - files of the comparison sorted out so far
+    /// * `unreadable_files` - files skipped because of an error
+    fn _filter_by_partial_hash(
+        &self,
+        source_files: Vec<FileEntry>,
+        dest_files: Vec<FileEntry>,
+        matching: &mut Matching,
+        unreadable_files: &mut Vec<UnreadableFile>,
+    ) -> Result<(Vec<FileEntry>, Vec<FileEntry>), ConfirmerError> {
+        // The blocks would cover the whole file, so the partial stage would not save anything
+        let min_size = max(self.partial_min_size, 2 * self.partial_block_size + 1);
+        let stage = HashStage::Partial { block_size: self.partial_block_size };
+        // Files from manifests have no partial checksum, so files of their size are hashed whole
+        let known_sizes: HashSet<Option<u64>> = source_files
+            .iter()
+            .chain(dest_files.iter())
+            .filter(|f| f.hash.is_some())
+            .map(|f| f.size)
+            .collect();
+        if known_sizes.contains(&None) {
+            return Ok((source_files, dest_files));
+        }
+        let needs_partial = |f: &FileEntry| {
+            f.size.map_or(false, |size| size >= min_size) && !known_sizes.contains(&f.size)
+        };
+        let (source_large, mut source_files): (Vec<FileEntry>, Vec<FileEntry>) =
+            source_files.into_iter().partition(needs_partial);
+        let (dest_large, mut dest_files): (Vec<FileEntry>, Vec<FileEntry>) =
+            dest_files.into_iter().partition(needs_partial);
+        if source_large.is_empty() {
+            if self.extra_files {
+                matching.extra.extend(dest_large.into_iter().map(|f| (f.location, f.path)));
+            }
+            return Ok((source_files, dest_files));
+        }
+
+        let source_hashes =
+            self._collect_hashes(source_large, stage, Phase::FileEnds, unreadable_files)?;
+        let (dest_linked, dest_large) = reuse_inode_hashes(&source_hashes, dest_large);
+        let mut dest_hashes =
+            self._collect_hashes(dest_large, stage, Phase::FileEnds, unreadable_files)?;
+        dest_hashes.extend(dest_linked);
+
+        let source_keys: HashSet<(Option<u64>, &String)> =
+            source_hashes.iter().map(|(f, hash)| (f.size, hash)).collect();
+        let dest_keys: HashSet<(Option<u64>, &String)> =
+            dest_hashes.iter().map(|(f, hash)| (f.size, hash)).collect();
+        let source_matched: Vec<bool> =
+            source_hashes.iter().map(|(f, hash)| dest_keys.contains(&(f.size, hash))).collect();
+        let dest_matched: Vec<bool> =
+            dest_hashes.iter().map(|(f, hash)| source_keys.contains(&(f.size, hash))).collect();
+
+        for ((file, _), matched) in source_hashes.into_iter().zip(source_matched) {
+            match matched {
+                true => source_files.push(file),
+                false => matching.unmatched.push(file),
+            }
+        }
+        for ((file, _), matched) in dest_hashes.into_iter().zip(dest_matched) {
+            match matched {
+                true => dest_files.push(file),
+                false if self.extra_files => matching.extra.push((file.location, file.path)),
+                false => {}
+            }
+        }
+        debug!(
+            "Partial checksums: {} source files and {} destination files left to hash",
+            source_files.len(),
+            dest_files.len()
+        );
+        Ok((source_files, dest_files))
+    }
+
+    /// Calculate checksums of files in the workers and wait for all of them
+    ///
+    /// Returns the files with their checksums or the first error encountered, unless errors are
+    /// skipped by [ErrorPolicy::SkipAndReport]
+    ///
+    /// # Arguments
+    /// * `files` - files to calculate checksums of
+    /// * `stage` - checksum to calculate
+    /// * `phase` - phase of the comparison
+    /// * `unreadable_files` - files skipped because of an error
+    fn _collect_hashes(
+        &self,
+        files: Vec<FileEntry>,
+        stage: HashStage,
+        phase: Phase,
+        unreadable_files: &mut Vec<UnreadableFile>,
+    ) -> Result<Vec<(FileEntry, String)>, ConfirmerError> {
+        let mut hashes = vec![];
+        self._run_hashes(files, stage, phase, |result| {
+            match result {
+                (file, Ok(hash)) => {
+                    hashes.extend(file.into_links().into_iter().map(|link| (link, hash.clone())))
+                }
+                (file, Err(e)) => self._handle_link_errors(file, e, unreadable_files)?,
             }
-            thread::sleep(2 * HUNDRED_MILIS);
+            Ok(true)
+        })?;
+        Ok(hashes)
+    }
+
+    /// Handle error of a file for the file and all its hardlinks, see
+    /// [_handle_file_error](CopyConfirmer::_handle_file_error)
+    fn _handle_link_errors(
+        &self,
+        file: FileEntry,
+        error: ConfirmerError,
+        unreadable_files: &mut Vec<UnreadableFile>,
+    ) -> Result<(), ConfirmerError> {
+        for link in file.hardlinks {
+            let link_error = error.with_path(PathBuf::from(&link.path));
+            self._handle_file_error(link.path, link_error, unreadable_files)?;
         }
-        if self.show_progress {
-            pbar.as_ref().unwrap().finish();
+        self._handle_file_error(file.path, error, unreadable_files)
+    }
+
+    /// Add file to `unreadable_files` or return the error, depending on the error policy
+    ///
+    /// # Arguments
+    /// * `path` - path of the file that could not be read
+    /// * `error` - error encountered when reading the file
+    /// * `unreadable_files` - files skipped because of an error
+    fn _handle_file_error(
+        &self,
+        path: OsString,
+        error: ConfirmerError,
+        unreadable_files: &mut Vec<UnreadableFile>,
+    ) -> Result<(), ConfirmerError> {
+        self.observers.error(&path, &error);
+        if self.error_policy == ErrorPolicy::Abort {
+            eprintln!("Error getting hash {:?}: {}", path, error);
+        }
+        skip_or_abort(self.error_policy, path, error, unreadable_files)
+    }
+
+    /// Calculate checksums of files in the workers and pass each result to `handle` as it
+    /// arrives
+    ///
+    /// Jobs are added to the bounded queue of the workers as their results arrive, so only a few
+    /// jobs and results are held at a time, up to the [capacity](Workers::capacity) of the
+    /// workers. The files given here are listed in full before the first job is added, files in
+    /// destinations are instead hashed while they are walked by
+    /// [_match_streamed_destinations](Self::_match_streamed_destinations) unless all of them
+    /// are needed. Once `handle` returns false or an error, no more jobs are added and the queued
+    /// jobs skip the calculation. Returns after the result of every job added has arrived, so that no result is
+    /// left for the next phase.
+    ///
+    /// Hardlinks of the same file get a single job, its result holds them in
+    /// [hardlinks](FileEntry::hardlinks).
+    ///
+    /// Notifies observers of the start of `phase` with the files and bytes to hash.
+    ///
+    /// # Arguments
+    /// * `files` - files to calculate checksums of
+    /// * `stage` - checksum to calculate
+    /// * `phase` - phase of the comparison
+    /// * `handle` - called with each result, returns whether more results are needed
+    fn _run_hashes<F>(
+        &self,
+        files: Vec<FileEntry>,
+        stage: HashStage,
+        phase: Phase,
+        mut handle: F,
+    ) -> Result<(), ConfirmerError>
+    where
+        F: FnMut(HashResult) -> Result<bool, ConfirmerError>,
+    {
+        let files = group_hardlinks(files);
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let hash_jobs = self._hash_jobs(&cancelled);
+        let total_bytes = files.iter().map(|f| hash_jobs.bytes_to_read(f, stage)).sum();
+        self.observers.phase_changed(phase, files.len() as u64, total_bytes);
+
+        let mut jobs = files.into_iter().map(|file| hash_jobs.job(file, stage));
+        let mut next_job = jobs.next();
+        let mut pending: u64 = 0;
+        let mut outcome = Ok(());
+        loop {
+            if cancelled.load(Ordering::Relaxed) {
+                next_job = None;
+            }
+            // Jobs in flight are limited, so that the results are read before the workers get
+            // far past a result that stops the hashing
+            while pending < self.workers.capacity() as u64 {
+                let job = match next_job.take() {
+                    Some(job) => job,
+                    None => break,
+                };
+                match self.workers.try_execute(job) {
+                    Ok(()) => {
+                        pending += 1;
+                        next_job = jobs.next();
+                    }
+                    Err(job) => {
+                        next_job = Some(job);
+                        break;
+                    }
+                }
+            }
+            if pending == 0 {
+                break;
+            }
+
+            // Wait for a result, then take all results that arrived in the meantime
+            let first = self.hashes_rx.recv().expect("Channel of file hashes is closed");
+            for message in std::iter::once(first).chain(self.hashes_rx.try_iter()) {
+                pending -= 1;
+                match message {
+                    HashMessage::Hashed(Some(result)) => {
+                        handle_hash_result(result, &cancelled, &mut handle, &mut outcome)
+                    }
+                    HashMessage::Hashed(None) => {}
+                    _ => unreachable!("Only jobs send messages while files are hashed"),
+                }
+            }
+        }
+        outcome
+    }
+
+    /// Create jobs calculating checksums of files, see [HashJobs]
+    ///
+    /// # Arguments
+    /// * `cancelled` - flag to skip the calculation
+    fn _hash_jobs(&self, cancelled: &Arc<AtomicBool>) -> HashJobs {
+        HashJobs {
+            sender: self.hashes_tx.clone(),
+            cancelled: Arc::clone(cancelled),
+            cache: self.hash_cache.clone(),
+            hashed_files: Arc::clone(&self.hashed_files),
+            observers: self.observers.clone(),
+            algorithm: self.hash_algorithm,
+            source_ends: None,
         }
     }
 }
 
-/// Get number of files in directory
-fn get_total_files(dir: &OsStr) -> u64 {
-    WalkDir::new(dir)
-        .follow_root_links(false)
-        .into_iter()
-        .filter_map(|x| x.ok())
-        .filter(|x| x.file_type().is_file())
-        .count() as u64
+/// Pass result of a job to `handle` unless the hashing is cancelled
+///
+/// The hashing is cancelled once `handle` returns false or an error, the error is kept in
+/// `outcome`. A panic of the job fails the comparison.
+fn handle_hash_result<F>(
+    result: HashResult,
+    cancelled: &AtomicBool,
+    handle: &mut F,
+    outcome: &mut Result<(), ConfirmerError>,
+) where
+    F: FnMut(HashResult) -> Result<bool, ConfirmerError>,
+{
+    if cancelled.load(Ordering::Relaxed) {
+        return;
+    }
+    let needs_more = match result {
+        (_, Err(ConfirmerError::ThreadPanic)) => Err(ConfirmerError::ThreadPanic),
+        result => handle(result),
+    };
+    match needs_more {
+        Ok(true) => {}
+        Ok(false) => cancelled.store(true, Ordering::Relaxed),
+        Err(e) => {
+            *outcome = Err(e);
+            cancelled.store(true, Ordering::Relaxed);
+        }
+    }
 }
 
-/// Get tuple of hash and path
-fn get_hash(path: OsString) -> IoResult<String> {
-    let checksum = get_blake2_checksum(&path)?;
-    Ok(checksum)
+/// Give hardlink the checksum of the file it links to, or the error of the file with its path
+fn link_result(link: FileEntry, hash: &Result<String, ConfirmerError>) -> HashResult {
+    let hash = match hash {
+        Ok(hash) => Ok(hash.clone()),
+        Err(e) => Err(e.with_path(PathBuf::from(&link.path))),
+    };
+    (link, hash)
 }
 
-/// Returns true if path contains one of excluded patterns
-fn is_path_excluded(path: &OsStr, excluded_patterns: &Vec<ExcludePattern>) -> bool {
-    use ExcludePattern::*;
-    let path_str = path.to_str().expect("Could not decode path string.");
-    for pattern in excluded_patterns {
-        match pattern {
-            MatchEverywhere(part) => {
-                if path_str.contains(part) {
-                    return true;
-                }
+/// Add file to `unreadable_files` with [ErrorPolicy::SkipAndReport], return the error otherwise
+///
+/// # Arguments
+/// * `error_policy` - what to do with the error
+/// * `path` - path of the file that could not be read
+/// * `error` - error encountered when reading the file
+/// * `unreadable_files` - vector to add the skipped file to
+fn skip_or_abort(
+    error_policy: ErrorPolicy,
+    path: OsString,
+    error: ConfirmerError,
+    unreadable_files: &mut Vec<UnreadableFile>,
+) -> Result<(), ConfirmerError> {
+    match error_policy {
+        ErrorPolicy::Abort => Err(error),
+        ErrorPolicy::SkipAndReport => {
+            debug!("Skipping unreadable file {:?}: {}", path, error);
+            unreadable_files.push(UnreadableFile { path, error });
+            Ok(())
+        }
+    }
+}
+
+/// Move files sharing device and inode with an earlier file to its hardlinks
+///
+/// Files of unknown inode and files with known checksum are left as they are.
+fn group_hardlinks(files: Vec<FileEntry>) -> Vec<FileEntry> {
+    let mut grouped: Vec<FileEntry> = Vec::with_capacity(files.len());
+    let mut first_links: HashMap<(u64, u64), usize> = HashMap::new();
+    for file in files {
+        if file.inode == 0 || file.hash.is_some() {
+            grouped.push(file);
+            continue;
+        }
+        match first_links.entry((file.device, file.inode)) {
+            Entry::Occupied(entry) => grouped[*entry.get()].hardlinks.push(file),
+            Entry::Vacant(entry) => {
+                entry.insert(grouped.len());
+                grouped.push(file);
             }
+        }
+    }
+    grouped
+}
 
-            MatchPathStart(part) => {
-                if path_str.starts_with(part) {
-                    return true;
+/// Take files sharing device and inode with one of `hashed` files out of `files` with its
+/// checksum, so that each file is hashed only once even if it is both in source and destination
+///
+/// Returns the files taken with their checksums and the files left. Files of unknown inode and
+/// files with known checksum are left.
+fn reuse_inode_hashes(
+    hashed: &[(FileEntry, String)],
+    files: Vec<FileEntry>,
+) -> (Vec<(FileEntry, String)>, Vec<FileEntry>) {
+    let known: HashMap<(u64, u64), &String> = hashed
+        .iter()
+        .filter(|(file, _)| file.inode != 0)
+        .map(|(file, hash)| ((file.device, file.inode), hash))
+        .collect();
+    let mut reused = vec![];
+    let mut left = vec![];
+    for file in files {
+        let hash = match file.inode == 0 || file.hash.is_some() {
+            true => None,
+            false => known.get(&(file.device, file.inode)),
+        };
+        match hash {
+            Some(hash) => reused.push((file, (*hash).clone())),
+            None => left.push(file),
+        }
+    }
+    (reused, left)
+}
+
+/// Get sorted groups of paths sharing device and inode, see [ConfirmerResult::hardlinks]
+///
+/// # Arguments
+/// * `files` - device and inode number with path of each file of known inode
+fn hardlink_groups(files: Vec<((u64, u64), OsString)>) -> Vec<Vec<OsString>> {
+    let mut links: HashMap<(u64, u64), Vec<OsString>> = HashMap::new();
+    for (key, path) in files {
+        links.entry(key).or_default().push(path);
+    }
+    let mut groups: Vec<Vec<OsString>> = links.into_values().filter(|g| g.len() > 1).collect();
+    groups.iter_mut().for_each(|group| group.sort());
+    groups.sort();
+    groups
+}
+
+/// Get checksum of the file for given stage
+///
+/// Checksums of whole files are looked up in and stored to `cache` if there is one. Checksum
+/// already known for the file is returned right away. `on_read` is called with the number of
+/// bytes after each read of the file.
+fn get_hash(
+    file: &FileEntry,
+    stage: HashStage,
+    algorithm: HashAlgorithm,
+    cache: Option<&HashCache>,
+    on_read: &dyn Fn(u64),
+) -> Result<String, ConfirmerError> {
+    let checksum = match (stage, cache) {
+        (HashStage::Full, _) if file.hash.is_some() => file.hash.clone().unwrap(),
+        (HashStage::Partial { block_size }, _) => {
+            get_partial_checksum(&file.path, block_size, algorithm, on_read)?
+        }
+        (HashStage::Full, None) => get_checksum(&file.path, algorithm, on_read)?,
+        (HashStage::Full, Some(cache)) => {
+            let stamp = file.stamp();
+            match cache.get(&file.path, &stamp, algorithm) {
+                Some(checksum) => checksum,
+                None => {
+                    let checksum = get_checksum(&file.path, algorithm, on_read)?;
+                    cache.insert(&file.path, &stamp, algorithm, &checksum);
+                    checksum
                 }
             }
         }
-    }
+    };
+    Ok(checksum)
+}
+
+/// Get device number of the file, zero on platforms without inodes
+#[cfg(unix)]
+fn get_device(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.dev()
+}
+
+/// Get device number of the file, zero on platforms without inodes
+#[cfg(not(unix))]
+fn get_device(_metadata: &Metadata) -> u64 {
+    0
+}
 
-    false
+/// Get inode number of the file, zero on platforms without inodes
+#[cfg(unix)]
+fn get_inode(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.ino()
+}
+
+/// Get inode number of the file, zero on platforms without inodes
+#[cfg(not(unix))]
+fn get_inode(_metadata: &Metadata) -> u64 {
+    0
+}
+
+/// Get number of hardlinks of the file, zero on platforms without inodes
+#[cfg(unix)]
+fn get_links(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.nlink()
+}
+
+/// Get number of hardlinks of the file, zero on platforms without inodes
+#[cfg(not(unix))]
+fn get_links(_metadata: &Metadata) -> u64 {
+    0
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::collections::HashSet;
 
     #[test]
     fn test_exclusion_match_path_start() -> Result<(), ConfirmerError> {
         // Excludes foo subdir
         let excluded_pattern_1 =
-            ExcludePattern::MatchPathStart(String::from("tests/fixtures/exclusion/dir_A/foo"));
+            ExcludePattern::MatchPathStart(OsString::from("tests/fixtures/exclusion/dir_A/foo"));
         // Will not exclude anything
-        let excluded_pattern_2 = ExcludePattern::MatchPathStart(String::from("/bar"));
+        let excluded_pattern_2 = ExcludePattern::MatchPathStart(OsString::from("/bar"));
         let cc = CopyConfirmer::new(1)
             .add_excluded_pattern(excluded_pattern_1)
             .add_excluded_pattern(excluded_pattern_2);
@@ -435,8 +2229,9 @@ mod tests {
             &[String::from("tests/fixtures/exclusion/dir_B")],
         )?;
 
-        let expected_missing = vec!["tests/fixtures/exclusion/dir_A/bar/foo.txt".into()];
-        assert_eq!(result, ConfirmerResult::MissingFiles(expected_missing));
+        let expected_missing: Vec<OsString> =
+            vec!["tests/fixtures/exclusion/dir_A/bar/foo.txt".into()];
+        assert_eq!(result.missing, expected_missing);
 
         let excluded = cc.get_excluded_paths();
         let expected_excluded: Vec<OsString> = vec![
@@ -451,6 +2246,395 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_all_copies() -> Result<(), ConfirmerError> {
+        let cc = CopyConfirmer::new(2).with_all_copies();
+        let result =
+            cc.compare("tests/fixtures/dir_B", &["tests/fixtures/dir_A", "tests/fixtures/dir_B"])?;
+
+        assert!(result.is_complete(), "Expected all files to be found, got {:?}", result);
+        let found = result.found;
+        assert_eq!(found.len(), 1);
+        let file_found = found.values().next().unwrap();
+        assert_eq!(file_found.src_paths, vec![OsString::from("tests/fixtures/dir_B/foo.txt")]);
+        assert_eq!(
+            HashSet::<&OsString>::from_iter(file_found.dest_paths.iter()),
+            HashSet::from_iter([
+                &"tests/fixtures/dir_A/foo.txt".into(),
+                &"tests/fixtures/dir_B/foo.txt".into()
+            ])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_size_prefilter() -> Result<(), ConfirmerError> {
+        // `short.txt` has the size of `other.txt`, `long.txt` has no file of the same size
+        let cc = CopyConfirmer::new(2);
+        let result = cc.compare("tests/fixtures/size/dir_A", &["tests/fixtures/size/dir_B"])?;
+
+        let expected_missing: Vec<OsString> = vec![
+            "tests/fixtures/size/dir_A/long.txt".into(),
+            "tests/fixtures/size/dir_A/short.txt".into(),
+        ];
+        assert_eq!(result.missing, expected_missing);
+        assert_eq!(result.found.len(), 1);
+        assert_eq!(result.stats.source_files, 3);
+        assert_eq!(result.stats.dest_files, 2);
+        // Destinations are walked while hashed, so all files in source get hashed
+        assert_eq!(result.stats.hashed_files, 5);
+
+        // `long.txt` is not hashed once destinations are walked first
+        let cc = CopyConfirmer::new(2).with_all_copies();
+        let result = cc.compare("tests/fixtures/size/dir_A", &["tests/fixtures/size/dir_B"])?;
+        assert_eq!(result.missing, expected_missing);
+        assert_eq!(result.stats.hashed_files, 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_partial_hash() -> Result<(), ConfirmerError> {
+        // All files have the same size, `head_differs.txt` is ruled out by its partial checksum
+        let cc = CopyConfirmer::new(2).with_partial_hash(4, 10);
+        let result =
+            cc.compare("tests/fixtures/partial/dir_A", &["tests/fixtures/partial/dir_B"])?;
+
+        let expected_missing: Vec<OsString> = vec![
+            "tests/fixtures/partial/dir_A/head_differs.txt".into(),
+            "tests/fixtures/partial/dir_A/middle_differs.txt".into(),
+        ];
+        assert_eq!(result.missing, expected_missing);
+        // Ends and whole of all files in source, destination files are hashed whole after their
+        // ends match
+        assert_eq!(result.stats.hashed_files, 10);
+
+        let cc = cc.with_all_copies();
+        let result =
+            cc.compare("tests/fixtures/partial/dir_A", &["tests/fixtures/partial/dir_B"])?;
+        assert_eq!(result.missing, expected_missing);
+        assert_eq!(result.stats.hashed_files, 9);
+        Ok(())
+    }
+
+    #[test]
+    fn test_strict_paths() -> Result<(), ConfirmerError> {
+        // `sub/moved.txt` is copied to the root of `dir_B`
+        let source = "tests/fixtures/moved/dir_A";
+        let destinations = ["tests/fixtures/moved/dir_B"];
+        let expected_missing = vec![OsString::from("tests/fixtures/moved/dir_A/gone.txt")];
+
+        let result = CopyConfirmer::new(1).compare(source, &destinations)?;
+        assert_eq!(result.missing, expected_missing);
+        assert_eq!(result.found.len(), 2);
+        assert!(result.moved.is_empty());
+
+        let result = CopyConfirmer::new(1).with_strict_paths().compare(source, &destinations)?;
+        assert_eq!(result.missing, expected_missing);
+        assert_eq!(result.found.len(), 1);
+        let moved: Vec<&FileFound> = result.moved.values().collect();
+        assert_eq!(
+            moved,
+            vec![&FileFound {
+                src_paths: vec!["tests/fixtures/moved/dir_A/sub/moved.txt".into()],
+                dest_paths: vec!["tests/fixtures/moved/dir_B/moved.txt".into()],
+                dest_locations: vec![0],
+                dest_sources: vec![],
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_strict_paths_found() -> Result<(), ConfirmerError> {
+        // `x` is copied to the same path in `d1` and to another path in `d2`
+        let tmp = tempfile::tempdir().unwrap();
+        let source = tmp.path().join("source");
+        std::fs::create_dir_all(source.join("a")).unwrap();
+        std::fs::write(source.join("a/x"), "x").unwrap();
+        let (d1, d2) = (tmp.path().join("d1"), tmp.path().join("d2"));
+        std::fs::create_dir_all(d1.join("a")).unwrap();
+        std::fs::create_dir_all(d2.join("b")).unwrap();
+        std::fs::write(d1.join("a/x"), "x").unwrap();
+        std::fs::write(d2.join("b/x"), "x").unwrap();
+
+        let cc = CopyConfirmer::new(1).with_strict_paths().with_all_copies();
+        for destinations in [[&d1, &d2], [&d2, &d1]] {
+            let result = cc.compare(&source, &destinations)?;
+            assert!(result.is_complete());
+            let found: Vec<&FileFound> = result.found.values().collect();
+            assert_eq!(found.len(), 1);
+            assert_eq!(found[0].dest_paths, vec![d1.join("a/x").into_os_string()]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_extra_files() -> Result<(), ConfirmerError> {
+        let source = "tests/fixtures/moved/dir_A";
+        let destinations = ["tests/fixtures/moved/dir_B", "tests/fixtures/dir_B"];
+
+        let cc = CopyConfirmer::new(2).with_extra_files();
+        let result = cc.compare(source, &destinations)?;
+        let expected_extra: Vec<Vec<OsString>> =
+            vec![vec![], vec!["tests/fixtures/dir_B/foo.txt".into()]];
+        assert_eq!(result.extra, expected_extra);
+
+        // `moved.txt` is at a different path in source
+        let cc = CopyConfirmer::new(2).with_extra_files().with_strict_paths();
+        let result = cc.compare(source, &destinations)?;
+        let expected_extra: Vec<Vec<OsString>> = vec![
+            vec!["tests/fixtures/moved/dir_B/moved.txt".into()],
+            vec!["tests/fixtures/dir_B/foo.txt".into()],
+        ];
+        assert_eq!(result.extra, expected_extra);
+
+        let result = CopyConfirmer::new(2).compare(source, &destinations)?;
+        assert!(result.extra.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_coverage() -> Result<(), ConfirmerError> {
+        let cc = CopyConfirmer::new(2).with_all_copies();
+        let result =
+            cc.compare("tests/fixtures/dir_A", &["tests/fixtures/dir_B", "tests/fixtures/dir_A"])?;
+
+        let foo_size = std::fs::metadata("tests/fixtures/dir_A/foo.txt").unwrap().len();
+        assert_eq!(result.coverage[0], DestinationCoverage { files: 1, bytes: foo_size });
+        assert_eq!(
+            result.coverage[1],
+            DestinationCoverage { files: 2, bytes: result.stats.source_bytes }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_coverage_strict_paths() -> Result<(), ConfirmerError> {
+        // `d2` has a copy of `a/x` only at another path
+        let tmp = tempfile::tempdir().unwrap();
+        let source = tmp.path().join("source");
+        let (d1, d2) = (tmp.path().join("d1"), tmp.path().join("d2"));
+        for (dir, paths) in
+            [(&source, ["a/x", "c/x"]), (&d1, ["a/x", "c/x"]), (&d2, ["b/x", "c/x"])]
+        {
+            for path in paths {
+                std::fs::create_dir_all(dir.join(path).parent().unwrap()).unwrap();
+                std::fs::write(dir.join(path), "x").unwrap();
+            }
+        }
+
+        let cc = CopyConfirmer::new(1).with_strict_paths().with_all_copies();
+        let result = cc.compare(&source, &[&d1, &d2])?;
+        assert_eq!(result.coverage[0], DestinationCoverage { files: 2, bytes: 2 });
+        assert_eq!(result.coverage[1], DestinationCoverage { files: 1, bytes: 1 });
+
+        let cc = CopyConfirmer::new(1).with_all_copies();
+        let result = cc.compare(&source, &[&d1, &d2])?;
+        assert_eq!(result.coverage[1], DestinationCoverage { files: 2, bytes: 2 });
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_copies() -> Result<(), ConfirmerError> {
+        let cc = CopyConfirmer::new(2).with_min_copies(2);
+        let result =
+            cc.compare("tests/fixtures/dir_B", &["tests/fixtures/dir_A", "tests/fixtures/dir_B"])?;
+        assert!(result.is_complete());
+        assert_eq!(result.found.values().next().unwrap().copies(), 2);
+
+        // `foo.txt` is only in `dir_A`
+        let result = cc.compare(
+            "tests/fixtures/dir_B",
+            &["tests/fixtures/dir_A", "tests/fixtures/moved/dir_A"],
+        )?;
+        assert!(!result.is_complete());
+        assert!(result.missing.is_empty());
+        let under_replicated: Vec<&FileFound> = result.under_replicated.values().collect();
+        assert_eq!(under_replicated.len(), 1);
+        assert_eq!(under_replicated[0].copies(), 1);
+        assert_eq!(
+            under_replicated[0].dest_paths,
+            vec![OsString::from("tests/fixtures/dir_A/foo.txt")]
+        );
+
+        let cc = CopyConfirmer::new(1).with_min_copies(3);
+        let result =
+            cc.compare("tests/fixtures/dir_B", &["tests/fixtures/dir_A", "tests/fixtures/dir_B"]);
+        assert!(matches!(result, Err(ConfirmerError::InvalidSettings(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_min_copies_strict_paths() -> Result<(), ConfirmerError> {
+        // `a/x` is copied to the same path only in `d1`, `c/x` with the same content in both
+        let tmp = tempfile::tempdir().unwrap();
+        let source = tmp.path().join("source");
+        let (d1, d2) = (tmp.path().join("d1"), tmp.path().join("d2"));
+        for (dir, paths) in
+            [(&source, ["a/x", "c/x"]), (&d1, ["a/x", "c/x"]), (&d2, ["b/x", "c/x"])]
+        {
+            for path in paths {
+                std::fs::create_dir_all(dir.join(path).parent().unwrap()).unwrap();
+                std::fs::write(dir.join(path), "x").unwrap();
+            }
+        }
+
+        let cc = CopyConfirmer::new(1).with_strict_paths().with_min_copies(2);
+        for destinations in [[&d1, &d2], [&d2, &d1]] {
+            let result = cc.compare(&source, &destinations)?;
+            assert!(!result.is_complete());
+            assert!(result.missing.is_empty());
+            let under_replicated: Vec<&FileFound> = result.under_replicated.values().collect();
+            assert_eq!(under_replicated.len(), 1);
+            assert_eq!(under_replicated[0].copies(), 1);
+
+            let mut copies: Vec<(OsString, usize)> = under_replicated[0]
+                .src_paths
+                .iter()
+                .cloned()
+                .zip(under_replicated[0].copy_locations().iter().map(Vec::len))
+                .collect();
+            copies.sort();
+            let expected_copies = vec![
+                (source.join("a/x").into_os_string(), 1),
+                (source.join("c/x").into_os_string(), 2),
+            ];
+            assert_eq!(copies, expected_copies);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_algorithms() -> Result<(), ConfirmerError> {
+        for algorithm in HashAlgorithm::ALL {
+            let cc = CopyConfirmer::new(1).with_hash_algorithm(algorithm);
+            let result = cc.compare("tests/fixtures/dir_B", &["tests/fixtures/dir_A"])?;
+
+            assert!(result.is_complete(), "Expected all files to be found with {}", algorithm);
+            assert_eq!(result.found.len(), 1, "{}", algorithm);
+        }
+        assert_eq!("sha256".parse::<HashAlgorithm>(), Ok(HashAlgorithm::Sha256));
+        assert!("md4".parse::<HashAlgorithm>().is_err());
+
+        // 64 bit FNV-1a implemented outside of the crate
+        struct Fnv1a(u64);
+        impl FileHasher for Fnv1a {
+            fn update(&mut self, data: &[u8]) {
+                for byte in data {
+                    self.0 = (self.0 ^ u64::from(*byte)).wrapping_mul(0x100000001b3);
+                }
+            }
+            fn finalize(self: Box<Self>) -> String {
+                format!("{:016x}", self.0)
+            }
+        }
+        let fnv1a = HashAlgorithm::Custom(CustomAlgorithm {
+            name: "fnv1a",
+            new_hasher: || Box::new(Fnv1a(0xcbf29ce484222325)),
+        });
+        let cc = CopyConfirmer::new(1).with_hash_algorithm(fnv1a);
+        let result = cc.compare("tests/fixtures/dir_B", &["tests/fixtures/dir_A"])?;
+        assert!(result.is_complete());
+        assert_eq!(result.found.keys().next().map(String::len), Some(16));
+        let manifest = cc.create_manifest("tests/fixtures/dir_B")?;
+        assert_eq!(manifest.algorithm.to_string(), "fnv1a");
+        let source = Location::Manifest(manifest);
+        let destinations = [Location::Dir("tests/fixtures/dir_A".into())];
+        assert!(cc.compare_locations(&source, &destinations)?.is_complete());
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_cache() -> Result<(), ConfirmerError> {
+        let tmp = tempfile::tempdir().unwrap();
+        let cache_path = tmp.path().join("hashes.json");
+
+        // Hash all files, so that the number of cached checksums does not depend on timing
+        let cc =
+            CopyConfirmer::new(1).with_all_copies().with_hash_cache(HashCache::open(&cache_path)?);
+        let result = cc.compare("tests/fixtures/dir_B", &["tests/fixtures/dir_A"])?;
+        assert!(result.is_complete());
+
+        // Replace the cached checksum of the copy, so the copy no longer matches
+        let mut cache_json: serde_json::Value =
+            serde_json::from_reader(std::fs::File::open(&cache_path).unwrap()).unwrap();
+        for (path, entry) in cache_json["entries"].as_object_mut().unwrap() {
+            if path.ends_with("dir_A/foo.txt") {
+                entry["hash"] = "bogus".into();
+            }
+        }
+        std::fs::write(&cache_path, cache_json.to_string()).unwrap();
+
+        let cache = HashCache::open(&cache_path)?;
+        assert_eq!(cache.len(), 3);
+        let cc = CopyConfirmer::new(1).with_hash_cache(cache);
+        let result = cc.compare("tests/fixtures/dir_B", &["tests/fixtures/dir_A"])?;
+        assert_eq!(result.missing, vec![OsString::from("tests/fixtures/dir_B/foo.txt")]);
+
+        // Checksum of a file deleted from the walked directory is removed
+        let dest = tmp.path().join("dest");
+        std::fs::create_dir(&dest).unwrap();
+        std::fs::copy("tests/fixtures/dir_B/foo.txt", dest.join("foo.txt")).unwrap();
+        std::fs::copy("tests/fixtures/dir_B/foo.txt", dest.join("deleted.txt")).unwrap();
+        let cc =
+            CopyConfirmer::new(1).with_all_copies().with_hash_cache(HashCache::open(&cache_path)?);
+        cc.compare(Path::new("tests/fixtures/dir_B"), &[&dest])?;
+        assert_eq!(HashCache::open(&cache_path)?.len(), 5);
+        std::fs::remove_file(dest.join("deleted.txt")).unwrap();
+        cc.compare(Path::new("tests/fixtures/dir_B"), &[&dest])?;
+        assert_eq!(HashCache::open(&cache_path)?.len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_manifest() -> Result<(), ConfirmerError> {
+        let tmp = tempfile::tempdir().unwrap();
+        let manifest_path = tmp.path().join("manifest.json");
+        let cc = CopyConfirmer::new(1);
+        cc.create_manifest("tests/fixtures/dir_A")?.save(&manifest_path)?;
+        let manifest = Manifest::load(&manifest_path)?;
+
+        let paths: Vec<&Path> = manifest.entries.iter().map(|e| e.path.as_path()).collect();
+        assert_eq!(paths, vec![Path::new("bar.txt"), Path::new("foo.txt")]);
+
+        // Manifest as destination
+        let source = Location::Dir("tests/fixtures/dir_B".into());
+        let result = cc.compare_locations(&source, &[manifest.clone().into()])?;
+        assert!(result.is_complete());
+
+        // Manifest as source
+        let destination = Location::Dir("tests/fixtures/dir_B".into());
+        let result = cc.compare_locations(&manifest.clone().into(), &[destination])?;
+        assert_eq!(result.missing, vec![OsString::from("tests/fixtures/dir_A/bar.txt")]);
+        assert_eq!(result.found.len(), 1);
+
+        // Manifest with checksums of different algorithm
+        let cc = CopyConfirmer::new(1).with_hash_algorithm(HashAlgorithm::Sha256);
+        assert!(cc.compare_locations(&source, &[manifest.into()]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_checksum_file() -> Result<(), ConfirmerError> {
+        let tmp = tempfile::tempdir().unwrap();
+        let sums_dir = tmp.path();
+        let sums_path = sums_dir.join("SHA256SUMS");
+
+        let cc = CopyConfirmer::new(1).with_hash_algorithm(HashAlgorithm::Sha256);
+        cc.create_manifest("tests/fixtures/dir_A")?.save_checksum_file(&sums_path)?;
+        let algorithm = HashAlgorithm::from_checksum_file_name("SHA256SUMS").unwrap();
+        let manifest = Manifest::load_checksum_file(&sums_path, algorithm)?;
+
+        assert_eq!(manifest.root, sums_dir);
+        assert_eq!(manifest.entries.len(), 2);
+        assert!(manifest.entries.iter().all(|e| e.size.is_none()));
+
+        let source = Location::Dir("tests/fixtures/dir_B".into());
+        let result = cc.compare_locations(&source, &[manifest.into()])?;
+        assert!(result.is_complete());
+        Ok(())
+    }
+
     #[test]
     fn test_exclusion_match_everywhere() -> Result<(), ConfirmerError> {
         // Excludes foo subdir
@@ -462,8 +2646,9 @@ mod tests {
             &[String::from("tests/fixtures/exclusion/dir_B")],
         )?;
 
-        let expected_missing = vec!["tests/fixtures/exclusion/dir_A/foo/foo.txt".into()];
-        assert_eq!(result, ConfirmerResult::MissingFiles(expected_missing));
+        let expected_missing: Vec<OsString> =
+            vec!["tests/fixtures/exclusion/dir_A/foo/foo.txt".into()];
+        assert_eq!(result.missing, expected_missing);
 
         let excluded = cc.get_excluded_paths();
         let expected_excluded: Vec<OsString> = vec![
@@ -476,4 +2661,497 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn test_exclusion_glob_and_regex() -> Result<(), ConfirmerError> {
+        // Matches bar subdir at any level
+        let cc = CopyConfirmer::new(1).add_excluded_pattern(ExcludePattern::Glob("bar".into()));
+        let result =
+            cc.compare("tests/fixtures/exclusion/dir_A", &["tests/fixtures/exclusion/dir_B"])?;
+        let expected_missing: Vec<OsString> = vec![
+            "tests/fixtures/exclusion/dir_A/foo/bar.txt".into(),
+            "tests/fixtures/exclusion/dir_A/foo/foo.txt".into(),
+        ];
+        assert_eq!(result.missing, expected_missing);
+        assert_eq!(
+            result.excluded,
+            vec![OsString::from("tests/fixtures/exclusion/dir_A/bar/foo.txt")]
+        );
+
+        // Glob with a slash is anchored to the source root
+        let cc = CopyConfirmer::new(1)
+            .add_excluded_pattern(ExcludePattern::Glob("foo/*.txt".into()))
+            .add_excluded_pattern(ExcludePattern::Glob("/*.txt".into()));
+        let result =
+            cc.compare("tests/fixtures/exclusion/dir_A", &["tests/fixtures/exclusion/dir_B"])?;
+        let expected_missing: Vec<OsString> =
+            vec!["tests/fixtures/exclusion/dir_A/bar/foo.txt".into()];
+        assert_eq!(result.missing, expected_missing);
+        assert_eq!(result.excluded.len(), 3);
+
+        let cc = CopyConfirmer::new(1)
+            .add_excluded_pattern(ExcludePattern::Regex(r"^foo/ba[rz]\.txt$".into()));
+        let result =
+            cc.compare("tests/fixtures/exclusion/dir_A", &["tests/fixtures/exclusion/dir_B"])?;
+        let expected_missing: Vec<OsString> = vec![
+            "tests/fixtures/exclusion/dir_A/bar/foo.txt".into(),
+            "tests/fixtures/exclusion/dir_A/foo/foo.txt".into(),
+        ];
+        assert_eq!(result.missing, expected_missing);
+        assert_eq!(result.excluded.len(), 2);
+
+        let cc = CopyConfirmer::new(1).add_excluded_pattern(ExcludePattern::Regex("(".into()));
+        let result =
+            cc.compare("tests/fixtures/exclusion/dir_A", &["tests/fixtures/exclusion/dir_B"]);
+        assert!(matches!(result, Err(ConfirmerError::InvalidSettings(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_exclusion_dest() -> Result<(), ConfirmerError> {
+        let cc = CopyConfirmer::new(1);
+        let result =
+            cc.compare("tests/fixtures/exclusion/dir_A", &["tests/fixtures/exclusion/dir_B"])?;
+        assert_eq!(result.missing.len(), 3);
+
+        // The only copy of foo/baz.txt is excluded in destination
+        let cc = CopyConfirmer::new(1)
+            .add_excluded_dest_pattern(ExcludePattern::MatchEverywhere("baz".into()));
+        let result =
+            cc.compare("tests/fixtures/exclusion/dir_A", &["tests/fixtures/exclusion/dir_B"])?;
+        assert_eq!(result.missing.len(), 4);
+        assert_eq!(result.stats.dest_files, 0);
+        assert!(result.excluded.is_empty());
+
+        // Pattern matching the destination root itself excludes nothing
+        let cc = CopyConfirmer::new(1)
+            .add_excluded_dest_pattern(ExcludePattern::MatchEverywhere("dir_B".into()));
+        let result = cc.compare("tests/fixtures/dir_A", &["tests/fixtures/dir_B"])?;
+        assert_eq!(result.stats.dest_files, 1);
+        assert_eq!(result.missing, vec![OsString::from("tests/fixtures/dir_A/bar.txt")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_include_filter() -> Result<(), ConfirmerError> {
+        let cc = CopyConfirmer::new(1)
+            .add_include_filter(IncludeFilter::Extensions(vec!["TXT".into()]))
+            .add_include_filter(IncludeFilter::MinSize(5));
+        let result = cc.compare("tests/fixtures/ignore/dir_A", &["tests/fixtures/ignore/dir_B"])?;
+        let expected_missing: Vec<OsString> =
+            vec!["tests/fixtures/ignore/dir_A/sub/local.txt".into()];
+        assert_eq!(result.missing, expected_missing);
+        assert_eq!(result.stats.source_files, 2);
+        assert_eq!(result.excluded.len(), 7);
+
+        let cc = CopyConfirmer::new(1)
+            .add_include_filter(IncludeFilter::ModifiedAfter(SystemTime::UNIX_EPOCH))
+            .add_include_filter(IncludeFilter::MaxSize(5));
+        let result = cc.compare("tests/fixtures/ignore/dir_A", &["tests/fixtures/ignore/dir_B"])?;
+        assert_eq!(result.stats.source_files, 2);
+
+        let cc = CopyConfirmer::new(1)
+            .add_include_filter(IncludeFilter::ModifiedBefore(SystemTime::UNIX_EPOCH));
+        let result = cc.compare("tests/fixtures/ignore/dir_A", &["tests/fixtures/ignore/dir_B"])?;
+        assert_eq!(result.stats.source_files, 0);
+
+        // Unknown size passes the filter
+        assert!(IncludeFilter::MinSize(5).matches(Path::new("foo"), None, None));
+        Ok(())
+    }
+
+    #[test]
+    fn test_ignore_files() -> Result<(), ConfirmerError> {
+        let cc = CopyConfirmer::new(1);
+        let result = cc.compare("tests/fixtures/ignore/dir_A", &["tests/fixtures/ignore/dir_B"])?;
+        assert_eq!(result.missing.len(), 4);
+
+        let cc = CopyConfirmer::new(1).with_ignore_files();
+        let result = cc.compare("tests/fixtures/ignore/dir_A", &["tests/fixtures/ignore/dir_B"])?;
+        assert!(result.is_complete(), "Expected all files to be found, got {:?}", result);
+        // Ignored directory is listed instead of its files, important.log is re-included
+        let expected_excluded: Vec<OsString> = vec![
+            "tests/fixtures/ignore/dir_A/build".into(),
+            "tests/fixtures/ignore/dir_A/debug.log".into(),
+            "tests/fixtures/ignore/dir_A/sub/local.txt".into(),
+            "tests/fixtures/ignore/dir_A/sub/other.log".into(),
+        ];
+        assert_eq!(
+            HashSet::<OsString>::from_iter(result.excluded.into_iter()),
+            HashSet::from_iter(expected_excluded.into_iter())
+        );
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_paths() -> Result<(), ConfirmerError> {
+        use std::os::unix::ffi::OsStringExt;
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("dir");
+        std::fs::create_dir(&dir).unwrap();
+        let name = OsString::from_vec(b"b\xffr.txt".to_vec());
+        std::fs::write(dir.join(&name), "bar").unwrap();
+
+        let cc = CopyConfirmer::new(1)
+            .add_excluded_pattern(ExcludePattern::MatchEverywhere("foo".into()))
+            .add_excluded_pattern(ExcludePattern::Regex("^foo".into()));
+        let manifest = cc.create_manifest(&dir)?;
+        let manifest_path = dir.with_extension("json");
+        manifest.save(&manifest_path)?;
+        let loaded = Manifest::load(&manifest_path)?;
+        let json = std::fs::read_to_string(&manifest_path).unwrap();
+
+        assert_eq!(loaded, manifest);
+        assert_eq!(loaded.entries[0].path, Path::new(&name));
+        assert!(json.contains(r#""base64": "Yv9yLnR4dA==""#), "Unexpected manifest {}", json);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_policy() -> Result<(), ConfirmerError> {
+        use std::os::unix::fs::symlink;
+        let tmp = tempfile::tempdir().unwrap();
+        let (source, dest) = (tmp.path().join("source"), tmp.path().join("dest"));
+        std::fs::create_dir_all(source.join("sub")).unwrap();
+        std::fs::create_dir_all(&dest).unwrap();
+        std::fs::write(source.join("a.txt"), "data").unwrap();
+        symlink("a.txt", source.join("link")).unwrap();
+        symlink("nowhere", source.join("dangling")).unwrap();
+        symlink("..", source.join("sub/up")).unwrap();
+        std::fs::write(dest.join("a.txt"), "data").unwrap();
+        symlink("a.txt", dest.join("link")).unwrap();
+
+        let compare =
+            |policy| CopyConfirmer::new(1).with_symlink_policy(policy).compare(&source, &[&dest]);
+        let skipped = compare(SymlinkPolicy::Skip);
+        let followed = compare(SymlinkPolicy::Follow);
+        let targets = compare(SymlinkPolicy::CompareTarget);
+        let (skipped, followed, targets) = (skipped?, followed?, targets?);
+
+        assert!(skipped.is_complete());
+        assert_eq!(skipped.stats.source_files, 1);
+        assert!(skipped.dangling_links.is_empty());
+
+        assert!(followed.is_complete(), "Expected all files to be found, got {:?}", followed);
+        assert_eq!(followed.stats.source_files, 2);
+        assert_eq!(followed.dangling_links, vec![source.join("dangling").into_os_string()]);
+        assert_eq!(followed.symlink_loops, vec![source.join("sub/up").into_os_string()]);
+        assert!(followed.hardlinks.is_empty());
+
+        // Link is found only as a link with the same target
+        let expected_missing =
+            vec![source.join("dangling").into_os_string(), source.join("sub/up").into_os_string()];
+        assert_eq!(targets.missing, expected_missing);
+        assert_eq!(targets.stats.source_files, 4);
+        assert_eq!(targets.found.len(), 2);
+        assert_eq!(targets.dangling_links, vec![source.join("dangling").into_os_string()]);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hardlinks() -> Result<(), ConfirmerError> {
+        let tmp = tempfile::tempdir().unwrap();
+        let (source, dest) = (tmp.path().join("source"), tmp.path().join("dest"));
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::create_dir_all(&dest).unwrap();
+        std::fs::write(source.join("a.txt"), "linked").unwrap();
+        std::fs::hard_link(source.join("a.txt"), source.join("b.txt")).unwrap();
+        std::fs::write(source.join("c.txt"), "single").unwrap();
+        std::fs::write(dest.join("a_copy.txt"), "linked").unwrap();
+        std::fs::hard_link(dest.join("a_copy.txt"), dest.join("a_link.txt")).unwrap();
+        std::fs::write(dest.join("c.txt"), "single").unwrap();
+        // Hardlink of a file in source is hashed only once in source
+        std::fs::hard_link(source.join("c.txt"), dest.join("c_link.txt")).unwrap();
+
+        let cc = CopyConfirmer::new(2).with_all_copies();
+        let result = cc.compare(&source, &[&dest])?;
+
+        assert!(result.is_complete(), "Expected all files to be found, got {:?}", result);
+        assert_eq!(result.stats.hashed_files, 4);
+        let expected_links: Vec<Vec<OsString>> = vec![
+            vec![dest.join("a_copy.txt").into(), dest.join("a_link.txt").into()],
+            vec![dest.join("c_link.txt").into(), source.join("c.txt").into()],
+            vec![source.join("a.txt").into(), source.join("b.txt").into()],
+        ];
+        assert_eq!(result.hardlinks, expected_links);
+        for found in result.found.values() {
+            assert_eq!(found.dest_paths.len(), 2);
+        }
+
+        // Checksums of file ends are shared the same way
+        let cc = CopyConfirmer::new(2).with_all_copies().with_partial_hash(1, 4);
+        let result = cc.compare(&source, &[&dest])?;
+        assert!(result.is_complete(), "Expected all files to be found, got {:?}", result);
+        assert_eq!(result.stats.hashed_files, 8);
+
+        // Hardlinks found while destinations are walked and hashed get the checksum of the first
+        // one. `missing.txt` keeps the walk going to the end.
+        let links = tmp.path().join("links");
+        std::fs::create_dir_all(&links).unwrap();
+        std::fs::write(links.join("link1.txt"), "linked").unwrap();
+        std::fs::hard_link(links.join("link1.txt"), links.join("link2.txt")).unwrap();
+        std::fs::write(source.join("missing.txt"), "missing").unwrap();
+        let result = CopyConfirmer::new(2).compare(&source, &[&links])?;
+        let expected_missing: Vec<OsString> =
+            vec![source.join("c.txt").into(), source.join("missing.txt").into()];
+        assert_eq!(result.missing, expected_missing);
+        assert_eq!(result.stats.hashed_files, 4);
+        let found: Vec<&FileFound> = result.found.values().collect();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].dest_paths.len(), 2);
+        let expected_links: Vec<OsString> =
+            vec![links.join("link1.txt").into(), links.join("link2.txt").into()];
+        assert!(result.hardlinks.contains(&expected_links));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_walk() -> Result<(), ConfirmerError> {
+        let destinations = ["tests/fixtures/moved/dir_B", "tests/fixtures/dir_B"];
+        let sequential =
+            CopyConfirmer::new(2).compare("tests/fixtures/moved/dir_A", &destinations)?;
+        let parallel = CopyConfirmer::new(2)
+            .with_parallel_walk()
+            .compare("tests/fixtures/moved/dir_A", &destinations)?;
+        assert_eq!(parallel.missing, sequential.missing);
+        assert_eq!(parallel.found.len(), sequential.found.len());
+        assert_eq!(parallel.coverage, sequential.coverage);
+        assert_eq!(parallel.stats.dest_files, 3);
+
+        let result = CopyConfirmer::new(1)
+            .with_parallel_walk()
+            .compare("tests/fixtures/dir_A", &["tests/fixtures/does_not_exist"]);
+        assert!(matches!(result, Err(ConfirmerError::Walk { .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn test_progress() -> Result<(), ConfirmerError> {
+        let cc = CopyConfirmer::new(2);
+        let progress = cc.progress();
+        assert_eq!(progress.snapshot().phase, Phase::Idle);
+
+        // Destinations are hashed last, the numbers of the phase are kept
+        cc.compare("tests/fixtures/dir_A", &["tests/fixtures/dir_B"])?;
+        let snapshot = progress.snapshot();
+        assert_eq!(snapshot.phase, Phase::Idle);
+        assert_eq!((snapshot.files_done, snapshot.files_total), (1, 1));
+        assert_eq!((snapshot.bytes_done, snapshot.bytes_total), (9, 9));
+        assert_eq!(snapshot.worker_bytes.len(), 2);
+        assert_eq!(snapshot.worker_bytes.iter().sum::<u64>(), 9);
+
+        // Files from manifests are not read
+        let manifest = cc.create_manifest("tests/fixtures/dir_B")?;
+        let source = Location::Dir("tests/fixtures/dir_A".into());
+        cc.compare_locations(&source, &[Location::Manifest(manifest)])?;
+        let snapshot = progress.snapshot();
+        assert_eq!((snapshot.files_done, snapshot.files_total), (1, 1));
+        assert_eq!((snapshot.bytes_done, snapshot.bytes_total), (0, 0));
+
+        let snapshot = ProgressSnapshot {
+            phase: Phase::Source,
+            files_done: 1,
+            files_total: 4,
+            bytes_done: 100,
+            bytes_total: 400,
+            worker_bytes: vec![100, 0, 0, 0],
+            elapsed: Duration::from_secs(2),
+        };
+        assert_eq!(snapshot.bytes_per_sec(), 50.0);
+        assert_eq!(snapshot.eta(), Some(Duration::from_secs(6)));
+        assert_eq!(ProgressSnapshot::default().eta(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_observer() -> Result<(), ConfirmerError> {
+        #[derive(Default)]
+        struct Recorder {
+            phases: std::sync::Mutex<Vec<Phase>>,
+            discovered: AtomicU64,
+            hashed: AtomicU64,
+            bytes: AtomicU64,
+            matched: std::sync::Mutex<Vec<(OsString, OsString)>>,
+            missing: std::sync::Mutex<Vec<OsString>>,
+        }
+        impl Observer for Recorder {
+            fn phase_changed(&self, phase: Phase, _files_total: u64, _bytes_total: u64) {
+                self.phases.lock().unwrap().push(phase);
+            }
+            fn file_discovered(&self, _path: &OsStr, _size: Option<u64>) {
+                self.discovered.fetch_add(1, Ordering::Relaxed);
+            }
+            fn bytes_hashed(&self, bytes: u64) {
+                self.bytes.fetch_add(bytes, Ordering::Relaxed);
+            }
+            fn hash_finished(&self, _path: &OsStr, hash: Result<&str, &ConfirmerError>) {
+                assert!(hash.is_ok());
+                self.hashed.fetch_add(1, Ordering::Relaxed);
+            }
+            fn file_matched(&self, source: &OsStr, dest: &OsStr) {
+                self.matched.lock().unwrap().push((source.into(), dest.into()));
+            }
+            fn file_missing(&self, path: &OsStr) {
+                self.missing.lock().unwrap().push(path.into());
+            }
+        }
+
+        let recorder = Arc::new(Recorder::default());
+        let cc = CopyConfirmer::new(2).add_observer(recorder.clone());
+        cc.compare("tests/fixtures/dir_A", &["tests/fixtures/dir_B"])?;
+
+        let phases = vec![Phase::Walk, Phase::Source, Phase::Destinations, Phase::Idle];
+        assert_eq!(*recorder.phases.lock().unwrap(), phases);
+        assert_eq!(recorder.discovered.load(Ordering::Relaxed), 3);
+        assert_eq!(recorder.hashed.load(Ordering::Relaxed), 3);
+        assert_eq!(recorder.bytes.load(Ordering::Relaxed), 27);
+        let matched: Vec<(OsString, OsString)> =
+            vec![("tests/fixtures/dir_A/foo.txt".into(), "tests/fixtures/dir_B/foo.txt".into())];
+        assert_eq!(*recorder.matched.lock().unwrap(), matched);
+        let missing: Vec<OsString> = vec!["tests/fixtures/dir_A/bar.txt".into()];
+        assert_eq!(*recorder.missing.lock().unwrap(), missing);
+        Ok(())
+    }
+
+    #[test]
+    fn test_job_queue() -> Result<(), ConfirmerError> {
+        let tmp = tempfile::tempdir().unwrap();
+        let (source, dest) = (tmp.path().join("source"), tmp.path().join("dest"));
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::create_dir_all(&dest).unwrap();
+        std::fs::write(source.join("file.txt"), "content").unwrap();
+        // Many more files than fit in the queue, all of the size of the source file
+        for i in 0..100 {
+            std::fs::write(dest.join(format!("file{:03}.txt", i)), format!("{:07}", i)).unwrap();
+        }
+        std::fs::write(dest.join("file000.txt"), "content").unwrap();
+
+        // All destination files are hashed, the results of all jobs are consumed
+        let cc = CopyConfirmer::new(2).with_extra_files();
+        let result = cc.compare(&source, &[&dest])?;
+        assert!(result.is_complete());
+        assert_eq!(result.extra[0].len(), 99);
+        assert_eq!(result.stats.hashed_files, 101);
+
+        // Hashing of destinations stops once the source file is found, no result of the skipped
+        // jobs is left for the next comparison. Every file is a copy, so that the first result
+        // stops the hashing whatever the order of the directory listing is.
+        let copies = tmp.path().join("copies");
+        std::fs::create_dir_all(&copies).unwrap();
+        for i in 0..100 {
+            std::fs::write(copies.join(format!("file{:03}.txt", i)), "content").unwrap();
+        }
+        let cc = CopyConfirmer::new(2);
+        for _ in 0..3 {
+            let result = cc.compare(&source, &[&copies])?;
+            assert!(result.is_complete());
+            assert!(result.stats.hashed_files <= 1 + cc.workers.capacity() as u64);
+            // The walk of the destination stops as well
+            assert!(result.stats.dest_files <= 2 + cc.workers.capacity() as u64);
+        }
+
+        // The walk waits for the workers, files found while the workers are busy do not pile up
+        struct BlockingObserver {
+            dest: PathBuf,
+            released: AtomicBool,
+            discovered: AtomicU64,
+        }
+        impl Observer for BlockingObserver {
+            fn file_discovered(&self, path: &OsStr, _size: Option<u64>) {
+                if Path::new(path).starts_with(&self.dest) {
+                    self.discovered.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+            fn hash_started(&self, path: &OsStr, _bytes: u64) {
+                while Path::new(path).starts_with(&self.dest)
+                    && !self.released.load(Ordering::SeqCst)
+                {
+                    std::thread::sleep(std::time::Duration::from_millis(1));
+                }
+            }
+        }
+        let observer = Arc::new(BlockingObserver {
+            dest: dest.clone(),
+            released: AtomicBool::new(false),
+            discovered: AtomicU64::new(0),
+        });
+        let cc = CopyConfirmer::new(2).add_observer(observer.clone());
+        let capacity = cc.workers.capacity() as u64;
+        let (walked_source, walked_dest) = (source.clone(), dest.clone());
+        let comparison = std::thread::spawn(move || cc.compare(&walked_source, &[&walked_dest]));
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        assert!(observer.discovered.load(Ordering::SeqCst) <= 1 + capacity);
+        observer.released.store(true, Ordering::SeqCst);
+        let result = comparison.join().unwrap()?;
+        assert!(result.is_complete());
+
+        // A panic in a job fails the comparison, but the workers keep running
+        struct PanickingObserver;
+        impl Observer for PanickingObserver {
+            fn hash_started(&self, _path: &OsStr, _bytes: u64) {
+                panic!("Observer panicked");
+            }
+        }
+        let cc = CopyConfirmer::new(2).add_observer(Arc::new(PanickingObserver));
+        for _ in 0..2 {
+            let result = cc.compare(&source, &[&dest]);
+            assert!(matches!(result, Err(ConfirmerError::ThreadPanic)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_source_error() {
+        let cc = CopyConfirmer::new(1);
+        let result = cc.compare("tests/fixtures/does_not_exist", &["tests/fixtures/dir_B"]);
+        match result {
+            Err(ConfirmerError::Walk { path, source }) => {
+                assert_eq!(path, Path::new("tests/fixtures/does_not_exist"));
+                assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+            }
+            _ => panic!("Expected walk error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn test_error_policy() {
+        // File removed after the walk
+        let files = || {
+            ["tests/fixtures/dir_A/foo.txt", "tests/fixtures/dir_A/removed.txt"]
+                .into_iter()
+                .map(|path| FileEntry {
+                    path: path.into(),
+                    relative_path: PathBuf::new(),
+                    location: 0,
+                    size: Some(0),
+                    mtime: None,
+                    device: 0,
+                    inode: 0,
+                    links: 0,
+                    hash: None,
+                    hardlinks: vec![],
+                })
+                .collect()
+        };
+        let mut unreadable_files = vec![];
+
+        let cc = CopyConfirmer::new(1);
+        let result =
+            cc._collect_hashes(files(), HashStage::Full, Phase::Source, &mut unreadable_files);
+        assert!(matches!(result, Err(ConfirmerError::Open { .. })));
+        assert!(unreadable_files.is_empty());
+
+        let cc = CopyConfirmer::new(1).with_error_policy(ErrorPolicy::SkipAndReport);
+        let hashes = cc
+            ._collect_hashes(files(), HashStage::Full, Phase::Source, &mut unreadable_files)
+            .unwrap();
+        assert_eq!(hashes.len(), 1);
+        assert_eq!(hashes[0].0.path, OsString::from("tests/fixtures/dir_A/foo.txt"));
+        assert_eq!(unreadable_files.len(), 1);
+        assert_eq!(unreadable_files[0].path, OsString::from("tests/fixtures/dir_A/removed.txt"));
+        assert!(matches!(unreadable_files[0].error, ConfirmerError::Open { .. }));
+    }
 }
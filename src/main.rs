@@ -1,100 +1,408 @@
 use copy_confirmer::*;
 use std::cmp::max;
 use std::ffi::OsString;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, SystemTime};
 
 use clap::Parser;
 use colored::Colorize;
-use serde::Serialize;
+use indicatif::HumanBytes;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     /// Source directory
-    #[arg(long, short, required(true))]
-    source: OsString,
+    #[arg(long, short, required_unless_present_any = ["source_manifest", "source_sums"])]
+    source: Option<OsString>,
+
+    /// Manifest to use as source instead of a directory
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["source", "source_sums"])]
+    source_manifest: Option<PathBuf>,
+
+    /// Checksum file (e.g. SHA256SUMS) to use as source instead of a directory
+    #[arg(long, value_name = "FILE", conflicts_with = "source")]
+    source_sums: Option<PathBuf>,
 
     /// Destination directories
-    #[arg(long, short, required(true))]
+    #[arg(
+        long,
+        short,
+        required_unless_present_any = ["dest_manifest", "dest_sums", "write_manifest", "write_sums"]
+    )]
     destination: Vec<OsString>,
 
+    /// Manifests to use as destinations in addition to destination directories
+    #[arg(long, value_name = "FILE")]
+    dest_manifest: Vec<PathBuf>,
+
+    /// Checksum files (e.g. SHA256SUMS) to use as destinations in addition to destination
+    /// directories
+    #[arg(long, value_name = "FILE")]
+    dest_sums: Vec<PathBuf>,
+
+    /// Write manifest of the source directory to this file
+    #[arg(long, value_name = "FILE", requires = "source")]
+    write_manifest: Option<PathBuf>,
+
+    /// Write checksums of the source directory to this file in the format of sha256sum, b2sum,
+    /// etc. (depending on --hash)
+    #[arg(long, value_name = "FILE", requires = "source")]
+    write_sums: Option<PathBuf>,
+
     /// Number of threads for checksum calculation
-    #[arg(long, short, default_value_t = 1)]
+    #[arg(long, short, default_value = "1")]
     jobs: usize,
 
+    /// Hash algorithm used to compare files (blake2b, blake3, sha256, xxh3, crc32c, md5)
+    /// [default: algorithm of the manifests or blake2b]
+    #[arg(long)]
+    hash: Option<HashAlgorithm>,
+
+    /// Cache checksums of unchanged files in this file [default: copcon/hashes.json in user's
+    /// cache directory]
+    #[arg(long, value_name = "FILE")]
+    cache: Option<PathBuf>,
+
+    /// Do not reuse or store checksums in the cache
+    #[arg(long, conflicts_with_all = ["cache", "rehash_older_than"])]
+    no_cache: bool,
+
+    /// Recalculate cached checksums older than this (e.g. 30d, 12h, 15m, 60s)
+    #[arg(long, value_name = "AGE", value_parser = parse_duration)]
+    rehash_older_than: Option<Duration>,
+
     /// Print json output to this file
     #[arg(long, short)]
     out_file: Option<OsString>,
 
-    /// Print json with all files found if copy is confirmed
+    /// Print json with all files found in destinations
     #[arg(long, short = 'f')]
     print_found: bool,
 
+    /// Walk and hash all files in destinations to find every copy of source files
+    #[arg(long)]
+    all_copies: bool,
+
+    /// Require each source file to be at the same relative path in a destination
+    #[arg(long)]
+    strict_paths: bool,
+
+    /// List files in destinations without a counterpart in source
+    #[arg(long)]
+    extra_files: bool,
+
+    /// Require each source file to be in at least this many destinations
+    #[arg(long, value_name = "N", default_value = "1")]
+    min_copies: usize,
+
+    /// Print how many files of source are in each destination
+    #[arg(long)]
+    coverage: bool,
+
+    /// Print groups of files in source and destinations that are hardlinks of the same file
+    #[arg(long)]
+    hardlinks: bool,
+
+    /// Report files that cannot be read and continue instead of stopping on the first one
+    #[arg(long)]
+    skip_unreadable: bool,
+
+    /// What to do with symbolic links: skip them, follow them or compare their target paths
+    #[arg(long, value_name = "POLICY", value_parser = parse_symlink_policy, default_value = "skip")]
+    symlinks: SymlinkPolicy,
+
+    /// Walk source and all destinations in parallel, e.g. when they are on different disks
+    #[arg(long)]
+    parallel_walk: bool,
+
     /// Disable progress bar
-    #[arg(long, default_value_t = false)]
+    #[arg(long)]
     no_progress_bar: bool,
 
     /// Exclude pattern from being compared from src directory
     #[arg(long)]
     exclude: Vec<String>,
 
+    /// Exclude pattern from being compared from destination directories
+    #[arg(long, value_name = "PATTERN")]
+    exclude_dest: Vec<String>,
+
+    /// Exclude files matching gitignore-style glob relative to src directory, e.g. "**/*.tmp"
+    #[arg(long, value_name = "GLOB")]
+    exclude_glob: Vec<String>,
+
+    /// Exclude files whose path relative to src directory matches regex, e.g. "^cache/.*\.bin$"
+    #[arg(long, value_name = "REGEX")]
+    exclude_regex: Vec<String>,
+
+    /// Compare only files with these extensions, e.g. "jpg,png"
+    #[arg(long, value_name = "EXT", value_delimiter = ',')]
+    include_ext: Vec<String>,
+
+    /// Compare only files of at least this size (e.g. 100K, 5M, 1G)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    min_size: Option<u64>,
+
+    /// Compare only files of at most this size (e.g. 100K, 5M, 1G)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    max_size: Option<u64>,
+
+    /// Compare only files modified at or after this UTC time (YYYY-MM-DD or YYYY-MM-DDTHH:MM:SS)
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    modified_after: Option<SystemTime>,
+
+    /// Compare only files modified before this UTC time (YYYY-MM-DD or YYYY-MM-DDTHH:MM:SS)
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    modified_before: Option<SystemTime>,
+
+    /// Exclude files ignored by .gitignore, .ignore and .copconignore files in src directory
+    #[arg(long)]
+    ignore_files: bool,
+
     /// Print all files excluded from comparison to this file ("-" for stderr)
     #[arg(long)]
     print_excluded: Option<OsString>,
 }
 
-fn main() -> Result<(), ConfirmerError> {
+fn main() {
     env_logger::init();
 
     let args = Args::parse();
+    if let Err(e) = run(args) {
+        eprintln!("{} {}", "Error:".red().bold(), e);
+        process::exit(1);
+    }
+}
 
+/// Run the comparison as set up by command line arguments
+fn run(args: Args) -> Result<(), ConfirmerError> {
     let num_threads = max(1, args.jobs);
 
     let mut cc = match args.no_progress_bar {
         true => CopyConfirmer::new(num_threads),
         false => CopyConfirmer::new(num_threads).with_progress_bar(),
     };
+    let source_sums =
+        args.source_sums.as_ref().map(|path| load_checksum_file(path, args.hash)).transpose()?;
+    let source_manifest =
+        args.source_manifest.as_ref().map(Manifest::load).transpose()?.or(source_sums);
+    let mut dest_manifests =
+        args.dest_manifest.iter().map(Manifest::load).collect::<Result<Vec<_>, _>>()?;
+    for path in &args.dest_sums {
+        dest_manifests.push(load_checksum_file(path, args.hash)?);
+    }
+    // Use hash algorithm of the manifests unless it is given explicitly
+    let algorithm = args.hash.unwrap_or_else(|| {
+        source_manifest
+            .iter()
+            .chain(dest_manifests.iter())
+            .map(|manifest| manifest.algorithm)
+            .next()
+            .unwrap_or_default()
+    });
+    cc = cc.with_hash_algorithm(algorithm);
+    let cache_path = match args.no_cache {
+        true => None,
+        false => args.cache.or_else(default_cache_path),
+    };
+    if let Some(cache_path) = cache_path {
+        let mut cache = HashCache::open(cache_path)?;
+        if let Some(max_age) = args.rehash_older_than {
+            cache = cache.with_max_age(max_age);
+        }
+        cc = cc.with_hash_cache(cache);
+    }
+    if args.all_copies {
+        cc = cc.with_all_copies();
+    }
+    if args.strict_paths {
+        cc = cc.with_strict_paths();
+    }
+    cc = cc.with_min_copies(args.min_copies);
+    if args.extra_files {
+        cc = cc.with_extra_files();
+    }
+    if args.skip_unreadable {
+        cc = cc.with_error_policy(ErrorPolicy::SkipAndReport);
+    }
+    cc = cc.with_symlink_policy(args.symlinks);
+    if args.parallel_walk {
+        cc = cc.with_parallel_walk();
+    }
+
+    let mut source = match (args.source, source_manifest) {
+        (_, Some(manifest)) => Location::Manifest(manifest),
+        (Some(dir), None) => Location::Dir(dir),
+        (None, None) => unreachable!("Source is required by argument parser"),
+    };
+    let source_root = match &source {
+        Location::Dir(dir) => dir.clone(),
+        Location::Manifest(manifest) => manifest.root.clone().into_os_string(),
+    };
 
     for mut path in args.exclude {
         let pattern = if path.starts_with("/") {
-            let mut full_path = PathBuf::from(args.source.clone());
+            let mut full_path = PathBuf::from(source_root.clone());
             // Remove the leading slash - otherwise whole path gets replaced by `path`
             path.remove(0);
             full_path.push(path);
-            ExcludePattern::MatchPathStart(
-                full_path
-                    .into_os_string()
-                    .into_string()
-                    .expect("Badly formed source string or exclude string"),
-            )
+            ExcludePattern::MatchPathStart(full_path.into_os_string())
         } else {
             ExcludePattern::MatchEverywhere(path)
         };
         cc = cc.add_excluded_pattern(pattern);
     }
+    if args.ignore_files {
+        cc = cc.with_ignore_files();
+    }
+    if !args.include_ext.is_empty() {
+        let extensions = args.include_ext.iter().map(|e| e.trim_start_matches('.').to_string());
+        cc = cc.add_include_filter(IncludeFilter::Extensions(extensions.collect()));
+    }
+    if let Some(min_size) = args.min_size {
+        cc = cc.add_include_filter(IncludeFilter::MinSize(min_size));
+    }
+    if let Some(max_size) = args.max_size {
+        cc = cc.add_include_filter(IncludeFilter::MaxSize(max_size));
+    }
+    if let Some(time) = args.modified_after {
+        cc = cc.add_include_filter(IncludeFilter::ModifiedAfter(time));
+    }
+    if let Some(time) = args.modified_before {
+        cc = cc.add_include_filter(IncludeFilter::ModifiedBefore(time));
+    }
+    for glob in args.exclude_glob {
+        cc = cc.add_excluded_pattern(ExcludePattern::Glob(glob));
+    }
+    for regex in args.exclude_regex {
+        cc = cc.add_excluded_pattern(ExcludePattern::Regex(regex));
+    }
+
+    if args.write_manifest.is_some() || args.write_sums.is_some() {
+        let manifest = cc.create_manifest(&source_root)?;
+        if let Some(manifest_path) = args.write_manifest {
+            manifest.save(&manifest_path)?;
+            println!("Manifest written to {manifest_path:?}.");
+        }
+        if let Some(sums_path) = args.write_sums {
+            manifest.save_checksum_file(&sums_path)?;
+            println!("Checksums written to {sums_path:?}.");
+        }
+        // Compare the manifest so that the source is not hashed again
+        source = Location::Manifest(manifest);
+    }
+
+    let destinations: Vec<Location> = args
+        .destination
+        .into_iter()
+        .map(Location::Dir)
+        .chain(dest_manifests.into_iter().map(Location::Manifest))
+        .collect();
+
+    for mut path in args.exclude_dest {
+        if path.starts_with('/') {
+            // Remove the leading slash - otherwise whole path gets replaced by `path`
+            path.remove(0);
+            for dest in &destinations {
+                let full_path = location_root(dest).join(&path);
+                let pattern = ExcludePattern::MatchPathStart(full_path.into_os_string());
+                cc = cc.add_excluded_dest_pattern(pattern);
+            }
+        } else {
+            cc = cc.add_excluded_dest_pattern(ExcludePattern::MatchEverywhere(path));
+        }
+    }
 
-    match cc.compare(args.source, &args.destination)? {
-        ConfirmerResult::Ok(filelist) => {
+    let result = match destinations.is_empty() {
+        true => None,
+        false => Some(cc.compare_locations(&source, &destinations)?),
+    };
+    if let Some(result) = result {
+        if result.is_complete() {
             println!("All files present in destinations.");
-            if args.print_found {
-                let files_found = serde_json::to_string_pretty(&filelist).unwrap();
-
-                if let Some(out_file) = args.out_file {
-                    let mut file = File::create(out_file)?;
-                    file.write_all(&files_found.into_bytes())?;
-                } else {
-                    println!("{files_found}");
+        }
+        if !result.missing.is_empty() {
+            println!("{}", "Missing files:".red().bold());
+            for file in &result.missing {
+                println!("{file:?}");
+            }
+        }
+        if !result.moved.is_empty() {
+            println!("{}", "Moved files:".red().bold());
+            for moved in result.moved.values() {
+                for file in &moved.src_paths {
+                    println!("{file:?} -> {:?}", moved.dest_paths);
                 }
             }
         }
-        ConfirmerResult::MissingFiles(files) => {
-            println!("{}", "Missing files:".red().bold());
-            for file in files {
+        if !result.under_replicated.is_empty() {
+            println!("{}", "Under-replicated files:".red().bold());
+            for file_found in result.under_replicated.values() {
+                for (file, locations) in
+                    file_found.src_paths.iter().zip(file_found.copy_locations())
+                {
+                    if locations.len() >= args.min_copies {
+                        continue;
+                    }
+                    let dests: Vec<&Path> =
+                        locations.iter().map(|&i| location_root(&destinations[i])).collect();
+                    println!(
+                        "{file:?}: {} of {} copies in {dests:?}",
+                        dests.len(),
+                        args.min_copies
+                    );
+                }
+            }
+        }
+        if args.coverage {
+            print_coverage(&destinations, &result);
+        }
+        if args.hardlinks && !result.hardlinks.is_empty() {
+            println!("{}", "Hardlinked files:".bold());
+            for group in &result.hardlinks {
+                println!("{group:?}");
+            }
+        }
+        for (dest, extra) in destinations.iter().zip(&result.extra) {
+            if extra.is_empty() {
+                continue;
+            }
+            println!("{} {:?}:", "Extra files in".yellow().bold(), location_root(dest));
+            for file in extra {
                 println!("{file:?}");
             }
         }
+        if !result.unreadable.is_empty() {
+            println!("{}", "Unreadable files:".red().bold());
+            for file in &result.unreadable {
+                println!("{:?}: {}", file.path, file.error);
+            }
+        }
+        if !result.dangling_links.is_empty() {
+            println!("{}", "Dangling links:".yellow().bold());
+            for link in &result.dangling_links {
+                println!("{link:?}");
+            }
+        }
+        if !result.symlink_loops.is_empty() {
+            println!("{}", "Symlink loops:".yellow().bold());
+            for link in &result.symlink_loops {
+                println!("{link:?}");
+            }
+        }
+        if args.print_found {
+            let files_found = serde_json::to_string_pretty(&result.found).unwrap();
+
+            if let Some(out_file) = args.out_file {
+                fs::write(&out_file, files_found.into_bytes())
+                    .map_err(|source| ConfirmerError::Storage { path: out_file.into(), source })?;
+            } else {
+                println!("{files_found}");
+            }
+        }
     }
 
     let stderr_output_str = OsString::from("-");
@@ -104,12 +412,217 @@ fn main() -> Result<(), ConfirmerError> {
             println!("{:?}", cc.get_excluded_paths());
         }
         Some(out_file) => {
-            let mut file = File::create(out_file)?;
+            let storage_error =
+                |source| ConfirmerError::Storage { path: PathBuf::from(&out_file), source };
+            let mut file = File::create(&out_file).map_err(storage_error)?;
             for f in cc.get_excluded_paths() {
-                write!(file, "{f:?}\n");
+                writeln!(file, "{f:?}").map_err(storage_error)?;
             }
         }
         None => {}
     }
     Ok(())
 }
+
+/// Get directory of a destination for printing
+fn location_root(location: &Location) -> &Path {
+    match location {
+        Location::Dir(dir) => Path::new(dir),
+        Location::Manifest(manifest) => manifest.root.as_path(),
+    }
+}
+
+/// Print table with number and size of source files found in each destination
+fn print_coverage(destinations: &[Location], result: &ConfirmerResult) {
+    let percentage = |part: u64, total: u64| match total {
+        0 => 100.0,
+        _ => part as f64 * 100.0 / total as f64,
+    };
+    let names: Vec<String> =
+        destinations.iter().map(|dest| location_root(dest).display().to_string()).collect();
+    let width = names.iter().map(String::len).chain([11]).max().unwrap_or_default();
+
+    println!("{}", "Coverage of source:".bold());
+    println!(
+        "{:width$}  {:>10}  {:>12}  {:>8}  {:>8}",
+        "Destination", "Files", "Size", "Files %", "Size %"
+    );
+    for (name, coverage) in names.iter().zip(&result.coverage) {
+        println!(
+            "{:width$}  {:>10}  {:>12}  {:>7.1}%  {:>7.1}%",
+            name,
+            coverage.files,
+            HumanBytes(coverage.bytes).to_string(),
+            percentage(coverage.files, result.stats.source_files),
+            percentage(coverage.bytes, result.stats.source_bytes),
+        );
+    }
+}
+
+/// Load checksum file, guessing the hash algorithm from its name unless it is given
+fn load_checksum_file(
+    path: &Path,
+    algorithm: Option<HashAlgorithm>,
+) -> Result<Manifest, ConfirmerError> {
+    let algorithm = algorithm
+        .or_else(|| {
+            let file_name = path.file_name()?.to_str()?;
+            HashAlgorithm::from_checksum_file_name(file_name)
+        })
+        .ok_or_else(|| {
+            ConfirmerError::InvalidSettings(format!(
+                "Cannot guess hash algorithm of {path:?}, use --hash"
+            ))
+        })?;
+    Manifest::load_checksum_file(path, algorithm)
+}
+
+/// Parse duration given as a number with unit suffix (s, m, h, d or w)
+fn parse_duration(arg: &str) -> Result<Duration, String> {
+    let split = arg.find(|c: char| !c.is_ascii_digit()).unwrap_or(arg.len());
+    let (number, unit) = arg.split_at(split);
+    let number: u64 = number.parse().map_err(|_| format!("Invalid duration {arg:?}"))?;
+    let unit_secs = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("Invalid unit of duration {arg:?}, expected s, m, h, d or w")),
+    };
+    let secs =
+        number.checked_mul(unit_secs).ok_or_else(|| format!("Duration {arg:?} is too long"))?;
+    Ok(Duration::from_secs(secs))
+}
+
+/// Parse symlink policy given as skip, follow or target
+fn parse_symlink_policy(arg: &str) -> Result<SymlinkPolicy, String> {
+    match arg {
+        "skip" => Ok(SymlinkPolicy::Skip),
+        "follow" => Ok(SymlinkPolicy::Follow),
+        "target" => Ok(SymlinkPolicy::CompareTarget),
+        _ => Err(format!("Invalid symlink policy {arg:?}, expected skip, follow or target")),
+    }
+}
+
+/// Parse size given in bytes with optional binary unit suffix (K, M, G or T)
+fn parse_size(arg: &str) -> Result<u64, String> {
+    let split = arg.find(|c: char| !c.is_ascii_digit()).unwrap_or(arg.len());
+    let (number, unit) = arg.split_at(split);
+    let number: u64 = number.parse().map_err(|_| format!("Invalid size {arg:?}"))?;
+    let unit_bytes: u64 = match unit.trim_end_matches(['B', 'b']).to_ascii_uppercase().as_str() {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(format!("Invalid unit of size {arg:?}, expected K, M, G or T")),
+    };
+    number.checked_mul(unit_bytes).ok_or_else(|| format!("Size {arg:?} is too large"))
+}
+
+/// Parse UTC time given as YYYY-MM-DD or YYYY-MM-DDTHH:MM:SS
+fn parse_time(arg: &str) -> Result<SystemTime, String> {
+    let invalid = || format!("Invalid time {arg:?}, expected YYYY-MM-DD or YYYY-MM-DDTHH:MM:SS");
+    let (date, time) = match arg.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (arg, None),
+    };
+    let parse_fields = |s: &str, separator: char| -> Option<Vec<u64>> {
+        s.split(separator).map(|field| field.parse().ok()).collect()
+    };
+    let (year, month, day) = match parse_fields(date, '-').as_deref() {
+        Some(&[year, month, day]) => (year, month, day),
+        _ => return Err(invalid()),
+    };
+    let (hour, minute, second) = match time.map(|time| parse_fields(time, ':')) {
+        None => (0, 0, 0),
+        Some(Some(fields)) if fields.len() == 3 => (fields[0], fields[1], fields[2]),
+        Some(_) => return Err(invalid()),
+    };
+    if year < 1970 || !(1..=12).contains(&month) {
+        return Err(invalid());
+    }
+    if !(1..=days_in_month(year, month)).contains(&day) {
+        return Err(invalid());
+    }
+    if hour > 23 || minute > 59 || second > 59 {
+        return Err(invalid());
+    }
+
+    // Days since unix epoch of the civil date, counting years from March
+    let (year, month) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let days = 365 * year + year / 4 - year / 100 + year / 400 + day_of_year - 719468;
+    let secs = days * 24 * 60 * 60 + hour * 60 * 60 + minute * 60 + second;
+    Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Number of days in the month of the year, months are numbered from 1
+fn days_in_month(year: u64, month: u64) -> u64 {
+    let is_leap_year = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    match month {
+        2 if is_leap_year => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("0"), Ok(0));
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("100K"), Ok(100 * 1024));
+        assert_eq!(parse_size("5m"), Ok(5 * 1024 * 1024));
+        assert_eq!(parse_size("1GB"), Ok(1 << 30));
+        assert_eq!(parse_size("2Tb"), Ok(2 << 40));
+
+        assert!(parse_size("").is_err());
+        assert!(parse_size("K").is_err());
+        assert!(parse_size("-1").is_err());
+        assert!(parse_size("1.5M").is_err());
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("20000000T").is_err());
+    }
+
+    #[test]
+    fn test_parse_time() {
+        let time = |secs: u64| Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(parse_time("1970-01-01"), time(0));
+        assert_eq!(parse_time("2024-02-29"), time(1_709_164_800));
+        assert_eq!(parse_time("2024-03-01T12:30:15"), time(1_709_296_215));
+        assert_eq!(parse_time("2000-02-29 00:00:01"), time(951_782_401));
+        assert_eq!(parse_time("2023-12-31T23:59:59"), time(1_704_067_199));
+
+        assert!(parse_time("2024-02-31").is_err());
+        assert!(parse_time("2023-02-29").is_err());
+        assert!(parse_time("2100-02-29").is_err());
+        assert!(parse_time("2024-04-31").is_err());
+        assert!(parse_time("2024-13-01").is_err());
+        assert!(parse_time("2024-00-10").is_err());
+        assert!(parse_time("2024-01-00").is_err());
+        assert!(parse_time("1969-12-31").is_err());
+        assert!(parse_time("2024-01-01T24:00:00").is_err());
+        assert!(parse_time("2024-01-01T12:00").is_err());
+        assert!(parse_time("2024/01/01").is_err());
+        assert!(parse_time("yesterday").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("60s"), Ok(Duration::from_secs(60)));
+        assert_eq!(parse_duration("15m"), Ok(Duration::from_secs(15 * 60)));
+        assert_eq!(parse_duration("12h"), Ok(Duration::from_secs(12 * 60 * 60)));
+        assert_eq!(parse_duration("30d"), Ok(Duration::from_secs(30 * 24 * 60 * 60)));
+        assert_eq!(parse_duration("2w"), Ok(Duration::from_secs(14 * 24 * 60 * 60)));
+
+        assert!(parse_duration("30").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("1y").is_err());
+    }
+}
@@ -0,0 +1,92 @@
+//! Manifests of directory trees
+//!
+//! A manifest holds relative path, size, modification time and checksum of every file in a
+//! directory tree. It can be used in place of the directory in comparison, e.g. to confirm copies
+//! of a source that is no longer available.
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+
+use crate::paths::{deserialize_path, serialize_path};
+use crate::{ConfirmerError, HashAlgorithm};
+
+/// Version of the manifest file format
+const MANIFEST_VERSION: u32 = 1;
+
+/// Checksums of all files in a directory tree
+///
+/// Create it with [create_manifest](crate::CopyConfirmer::create_manifest) and compare it with
+/// [compare_locations](crate::CopyConfirmer::compare_locations).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Manifest {
+    /// Directory the manifest was created from
+    #[serde(serialize_with = "serialize_path", deserialize_with = "deserialize_path")]
+    pub root: PathBuf,
+    /// Hash algorithm of the checksums
+    pub algorithm: HashAlgorithm,
+    /// Files in the directory tree
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// Single file in a [Manifest]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    /// Path of the file relative to the root of the manifest
+    #[serde(serialize_with = "serialize_path", deserialize_with = "deserialize_path")]
+    pub path: PathBuf,
+    /// Size of the file in bytes, unknown for manifests loaded from checksum files
+    pub size: Option<u64>,
+    /// Modification time of the file
+    pub mtime: Option<SystemTime>,
+    /// Checksum of the file
+    pub hash: String,
+}
+
+/// Contents of the manifest file
+#[derive(Serialize, Deserialize)]
+struct ManifestFile<M> {
+    version: u32,
+    #[serde(flatten)]
+    manifest: M,
+}
+
+impl Manifest {
+    /// Load manifest from a json file
+    ///
+    /// # Arguments
+    /// * `path` - path to the manifest file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfirmerError> {
+        let path = path.as_ref();
+        let file = File::open(path)
+            .map_err(|source| ConfirmerError::Storage { path: path.into(), source })?;
+        let manifest_file: ManifestFile<Manifest> =
+            serde_json::from_reader(BufReader::new(file))
+                .map_err(|e| ConfirmerError::InvalidFile { path: path.into(), source: e.into() })?;
+        if manifest_file.version != MANIFEST_VERSION {
+            return Err(ConfirmerError::InvalidFile {
+                path: path.into(),
+                source: format!("Unsupported version {}", manifest_file.version).into(),
+            });
+        }
+        Ok(manifest_file.manifest)
+    }
+
+    /// Write manifest to a json file
+    ///
+    /// # Arguments
+    /// * `path` - path to the manifest file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ConfirmerError> {
+        let path = path.as_ref();
+        let manifest_file = ManifestFile { version: MANIFEST_VERSION, manifest: self };
+        let write = || -> io::Result<()> {
+            let mut writer = BufWriter::new(File::create(path)?);
+            serde_json::to_writer_pretty(&mut writer, &manifest_file)?;
+            writer.flush()
+        };
+        write().map_err(|source| ConfirmerError::Storage { path: path.into(), source })
+    }
+}
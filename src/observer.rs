@@ -0,0 +1,242 @@
+//! Events of comparisons
+//!
+//! Observers registered with [add_observer](crate::CopyConfirmer::add_observer) are notified of
+//! the progress of each comparison, e.g. to show it in a GUI or to log it in a service. The
+//! terminal progress bar enabled by [with_progress_bar](crate::CopyConfirmer::with_progress_bar)
+//! is an observer too.
+
+use std::ffi::OsStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+use crate::{ConfirmerError, Phase};
+
+/// Time period for redrawing the progress bar
+const TICK: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// Receiver of events of comparisons
+///
+/// All methods do nothing by default, so only the events of interest need to be implemented. The
+/// methods are called from the threads walking directories and calculating checksums, so they
+/// should return quickly.
+pub trait Observer: Send + Sync {
+    /// A phase of the comparison started, [Phase::Idle] is sent once the comparison ends
+    ///
+    /// # Arguments
+    /// * `phase` - phase that started
+    /// * `files_total` - number of files to process in the phase, zero for the walk
+    /// * `bytes_total` - number of bytes to read in the phase, zero for the walk
+    fn phase_changed(&self, _phase: Phase, _files_total: u64, _bytes_total: u64) {}
+
+    /// A file was found while walking a directory or listing a manifest
+    ///
+    /// # Arguments
+    /// * `path` - path of the file
+    /// * `size` - size of the file in bytes if known
+    fn file_discovered(&self, _path: &OsStr, _size: Option<u64>) {}
+
+    /// A file to hash was found after the phase started, the totals of the phase grow by it
+    ///
+    /// Sent in [Phase::Destinations] while destinations are walked, see
+    /// [compare](crate::CopyConfirmer::compare).
+    ///
+    /// # Arguments
+    /// * `path` - path of the file
+    /// * `bytes` - number of bytes to read, zero if the checksum is already known
+    fn hash_queued(&self, _path: &OsStr, _bytes: u64) {}
+
+    /// Checksum calculation of a file started
+    ///
+    /// # Arguments
+    /// * `path` - path of the file
+    /// * `bytes` - number of bytes to read, zero if the checksum is already known
+    fn hash_started(&self, _path: &OsStr, _bytes: u64) {}
+
+    /// Bytes of a file were read for its checksum
+    ///
+    /// Bytes not read for a file, e.g. with its checksum found in the
+    /// [HashCache](crate::HashCache), are sent once its calculation finishes.
+    fn bytes_hashed(&self, _bytes: u64) {}
+
+    /// Checksum calculation of a file finished
+    ///
+    /// # Arguments
+    /// * `path` - path of the file
+    /// * `hash` - checksum of the file, or the error if the file could not be read
+    fn hash_finished(&self, _path: &OsStr, _hash: Result<&str, &ConfirmerError>) {}
+
+    /// A file in source was found in a destination for the first time
+    ///
+    /// # Arguments
+    /// * `source` - path of the file in source
+    /// * `dest` - path of the copy in destination
+    fn file_matched(&self, _source: &OsStr, _dest: &OsStr) {}
+
+    /// A file in source is missing from all destinations, sent once the comparison ends
+    fn file_missing(&self, _path: &OsStr) {}
+
+    /// A file or directory could not be read
+    ///
+    /// With [ErrorPolicy::Abort](crate::ErrorPolicy::Abort) the comparison stops after the event.
+    fn error(&self, _path: &OsStr, _error: &ConfirmerError) {}
+}
+
+/// Observers notified of the same events
+#[derive(Clone, Default)]
+pub(crate) struct Observers(pub Vec<Arc<dyn Observer>>);
+
+impl Observer for Observers {
+    fn phase_changed(&self, phase: Phase, files_total: u64, bytes_total: u64) {
+        self.0.iter().for_each(|o| o.phase_changed(phase, files_total, bytes_total));
+    }
+
+    fn file_discovered(&self, path: &OsStr, size: Option<u64>) {
+        self.0.iter().for_each(|o| o.file_discovered(path, size));
+    }
+
+    fn hash_queued(&self, path: &OsStr, bytes: u64) {
+        self.0.iter().for_each(|o| o.hash_queued(path, bytes));
+    }
+
+    fn hash_started(&self, path: &OsStr, bytes: u64) {
+        self.0.iter().for_each(|o| o.hash_started(path, bytes));
+    }
+
+    fn bytes_hashed(&self, bytes: u64) {
+        self.0.iter().for_each(|o| o.bytes_hashed(bytes));
+    }
+
+    fn hash_finished(&self, path: &OsStr, hash: Result<&str, &ConfirmerError>) {
+        self.0.iter().for_each(|o| o.hash_finished(path, hash));
+    }
+
+    fn file_matched(&self, source: &OsStr, dest: &OsStr) {
+        self.0.iter().for_each(|o| o.file_matched(source, dest));
+    }
+
+    fn file_missing(&self, path: &OsStr) {
+        self.0.iter().for_each(|o| o.file_missing(path));
+    }
+
+    fn error(&self, path: &OsStr, error: &ConfirmerError) {
+        self.0.iter().for_each(|o| o.error(path, error));
+    }
+}
+
+/// Observer drawing a progress bar of each phase in the terminal
+///
+/// Shows number of files found while walking, and bytes hashed with throughput and estimated
+/// time left in the other phases.
+pub struct ProgressBarObserver {
+    /// Bar reused by all phases, so that the workers update it without taking a lock
+    bar: ProgressBar,
+    files_done: AtomicU64,
+    files_total: AtomicU64,
+    /// Files found are counted only in [Phase::Walk], not while destinations are walked and hashed
+    walking: AtomicBool,
+}
+
+impl Default for ProgressBarObserver {
+    fn default() -> Self {
+        Self {
+            bar: ProgressBar::hidden(),
+            files_done: AtomicU64::new(0),
+            files_total: AtomicU64::new(0),
+            walking: AtomicBool::new(false),
+        }
+    }
+}
+
+impl ProgressBarObserver {
+    /// Create observer, the bar is drawn once a phase starts
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Show number of files processed in the message of the bar
+    fn _update_message(&self) {
+        let done = self.files_done.load(Ordering::Relaxed);
+        let total = self.files_total.load(Ordering::Relaxed);
+        self.bar.set_message(format!("({}/{} files)", done, total));
+    }
+
+    /// Draw the bar of a new phase below the bars of the previous phases
+    ///
+    /// # Arguments
+    /// * `style` - style of the bar
+    /// * `length` - number of bytes to hash in the phase, none for the spinner of the walk
+    /// * `prefix` - description of the phase
+    fn _start(&self, style: ProgressStyle, length: Option<u64>, prefix: &'static str) {
+        // Set up the bar hidden, so that the bar of the last phase is not redrawn with it
+        self.bar.set_draw_target(ProgressDrawTarget::hidden());
+        self.bar.set_style(style);
+        self.bar.set_prefix(prefix);
+        self.bar.set_message("");
+        match length {
+            Some(length) => self.bar.set_length(length),
+            None => self.bar.unset_length(),
+        }
+        self.bar.reset();
+        self.bar.set_draw_target(ProgressDrawTarget::stderr());
+        self.bar.enable_steady_tick(TICK);
+    }
+}
+
+impl Observer for ProgressBarObserver {
+    fn phase_changed(&self, phase: Phase, files_total: u64, bytes_total: u64) {
+        // The bar of the last phase is left in the terminal, the next phase is drawn below it
+        self.bar.disable_steady_tick();
+        self.bar.finish();
+        self.files_done.store(0, Ordering::Relaxed);
+        self.files_total.store(files_total, Ordering::Relaxed);
+        self.walking.store(phase == Phase::Walk, Ordering::Relaxed);
+        let prefix = match phase {
+            Phase::Idle => {
+                self.bar.set_draw_target(ProgressDrawTarget::hidden());
+                return;
+            }
+            Phase::Walk => {
+                let style = ProgressStyle::with_template(
+                    "[{elapsed_precise}] {spinner:.cyan} {pos:>7} files found",
+                )
+                .unwrap();
+                self._start(style, None, "");
+                return;
+            }
+            Phase::FileEnds => "Checking file ends",
+            Phase::Source => "Checking files from source",
+            Phase::Destinations => "Checking files from destinations",
+        };
+        let style = ProgressStyle::with_template(
+            "[{elapsed_precise}] {bar:40.cyan/blue} {bytes:>10}/{total_bytes:10} \
+             {binary_bytes_per_sec:>12} ETA {eta:>3} {prefix} {msg}",
+        )
+        .unwrap()
+        .progress_chars("##-");
+        self._start(style, Some(bytes_total), prefix);
+        self._update_message();
+    }
+
+    fn file_discovered(&self, _path: &OsStr, _size: Option<u64>) {
+        if self.walking.load(Ordering::Relaxed) {
+            self.bar.inc(1);
+        }
+    }
+
+    fn hash_queued(&self, _path: &OsStr, bytes: u64) {
+        self.files_total.fetch_add(1, Ordering::Relaxed);
+        self.bar.inc_length(bytes);
+        self._update_message();
+    }
+
+    fn bytes_hashed(&self, bytes: u64) {
+        self.bar.inc(bytes);
+    }
+
+    fn hash_finished(&self, _path: &OsStr, _hash: Result<&str, &ConfirmerError>) {
+        self.files_done.fetch_add(1, Ordering::Relaxed);
+        self._update_message();
+    }
+}
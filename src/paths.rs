@@ -0,0 +1,211 @@
+//! Paths that need not be valid UTF-8
+//!
+//! Paths are written to json as strings if they are valid UTF-8. Other paths are written as their
+//! raw bytes in base64, tagged so that they can be told apart from the strings. On windows, the
+//! raw bytes are the UTF-16 units of the path encoded as WTF-8:
+//! ``` text
+//! {"base64": "Zm9vL2L/ci50eHQ="}
+//! ```
+
+use std::borrow::Cow;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::de::Error as _;
+use serde::ser::SerializeSeq;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Path as written to json
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum JsonPath<'a> {
+    Utf8(Cow<'a, str>),
+    Bytes { base64: String },
+}
+
+impl<'a> JsonPath<'a> {
+    fn from_path(path: &'a Path) -> Self {
+        match path.to_str() {
+            Some(path) => JsonPath::Utf8(Cow::Borrowed(path)),
+            None => JsonPath::Bytes { base64: BASE64.encode(path_to_bytes(path)) },
+        }
+    }
+
+    fn into_path(self) -> Result<PathBuf, base64::DecodeError> {
+        match self {
+            JsonPath::Utf8(path) => Ok(PathBuf::from(path.into_owned())),
+            JsonPath::Bytes { base64 } => Ok(path_from_bytes(BASE64.decode(base64)?)),
+        }
+    }
+}
+
+/// Serialize path losslessly, see [module documentation](self)
+pub(crate) fn serialize_path<S: Serializer>(path: &Path, s: S) -> Result<S::Ok, S::Error> {
+    JsonPath::from_path(path).serialize(s)
+}
+
+/// Deserialize path written by [serialize_path]
+pub(crate) fn deserialize_path<'de, D: Deserializer<'de>>(d: D) -> Result<PathBuf, D::Error> {
+    JsonPath::deserialize(d)?.into_path().map_err(D::Error::custom)
+}
+
+/// Serialize paths losslessly, see [module documentation](self)
+pub(crate) fn serialize_paths<S: Serializer>(paths: &[OsString], s: S) -> Result<S::Ok, S::Error> {
+    let mut seq = s.serialize_seq(Some(paths.len()))?;
+    for path in paths {
+        seq.serialize_element(&JsonPath::from_path(Path::new(path)))?;
+    }
+    seq.end()
+}
+
+/// Convert raw file name to path
+#[cfg(unix)]
+pub(crate) fn path_from_bytes(name: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(OsString::from_vec(name))
+}
+
+/// Convert raw file name to path
+///
+/// The bytes are decoded as WTF-8, see [path_to_bytes]
+#[cfg(windows)]
+pub(crate) fn path_from_bytes(name: Vec<u8>) -> PathBuf {
+    use std::os::windows::ffi::OsStringExt;
+    PathBuf::from(OsString::from_wide(&wtf8_to_wide(&name)))
+}
+
+/// Convert raw file name to path
+///
+/// Names that are not valid UTF-8 are decoded lossily on platforms other than unix and windows.
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn path_from_bytes(name: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&name).into_owned())
+}
+
+/// Get raw bytes of a path
+#[cfg(unix)]
+pub(crate) fn path_to_bytes(path: &Path) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    Cow::Borrowed(path.as_os_str().as_bytes())
+}
+
+/// Get raw bytes of a path
+///
+/// The UTF-16 units of the path are encoded as WTF-8, which is UTF-8 for valid paths and keeps
+/// unpaired surrogates as their own three-byte sequences.
+#[cfg(windows)]
+pub(crate) fn path_to_bytes(path: &Path) -> Cow<'_, [u8]> {
+    use std::os::windows::ffi::OsStrExt;
+    match path.to_str() {
+        Some(path) => Cow::Borrowed(path.as_bytes()),
+        None => Cow::Owned(wide_to_wtf8(path.as_os_str().encode_wide())),
+    }
+}
+
+/// Get raw bytes of a path
+///
+/// Paths that are not valid unicode are encoded lossily on platforms other than unix and windows.
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn path_to_bytes(path: &Path) -> Cow<'_, [u8]> {
+    match path.to_string_lossy() {
+        Cow::Borrowed(path) => Cow::Borrowed(path.as_bytes()),
+        Cow::Owned(path) => Cow::Owned(path.into_bytes()),
+    }
+}
+
+/// Encode UTF-16 units as WTF-8, unpaired surrogates are encoded like other code points
+#[cfg(any(windows, test))]
+fn wide_to_wtf8(units: impl Iterator<Item = u16>) -> Vec<u8> {
+    let mut bytes = vec![];
+    for unit in char::decode_utf16(units) {
+        let code_point = match unit {
+            Ok(c) => c as u32,
+            Err(e) => e.unpaired_surrogate() as u32,
+        };
+        match code_point {
+            0..=0x7F => bytes.push(code_point as u8),
+            0x80..=0x7FF => {
+                bytes.extend([0xC0 | (code_point >> 6) as u8, 0x80 | (code_point & 0x3F) as u8])
+            }
+            0x800..=0xFFFF => bytes.extend([
+                0xE0 | (code_point >> 12) as u8,
+                0x80 | ((code_point >> 6) & 0x3F) as u8,
+                0x80 | (code_point & 0x3F) as u8,
+            ]),
+            _ => bytes.extend([
+                0xF0 | (code_point >> 18) as u8,
+                0x80 | ((code_point >> 12) & 0x3F) as u8,
+                0x80 | ((code_point >> 6) & 0x3F) as u8,
+                0x80 | (code_point & 0x3F) as u8,
+            ]),
+        }
+    }
+    bytes
+}
+
+/// Decode WTF-8 to UTF-16 units, invalid sequences are replaced by U+FFFD
+#[cfg(any(windows, test))]
+fn wtf8_to_wide(bytes: &[u8]) -> Vec<u16> {
+    let mut units = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let (len, first) = match bytes[i] {
+            b @ 0x00..=0x7F => (1, b as u32),
+            b @ 0xC0..=0xDF => (2, (b & 0x1F) as u32),
+            b @ 0xE0..=0xEF => (3, (b & 0x0F) as u32),
+            b @ 0xF0..=0xF4 => (4, (b & 0x07) as u32),
+            _ => (0, 0),
+        };
+        let code_point = bytes
+            .get(i + 1..i + len)
+            .filter(|tail| tail.iter().all(|b| b & 0xC0 == 0x80))
+            .map(|tail| tail.iter().fold(first, |c, b| (c << 6) | (b & 0x3F) as u32))
+            .filter(|c| *c <= 0x10FFFF);
+        match code_point {
+            Some(c) if c >= 0x10000 => {
+                let c = c - 0x10000;
+                units.extend([0xD800 | (c >> 10) as u16, 0xDC00 | (c & 0x3FF) as u16]);
+                i += len;
+            }
+            Some(c) => {
+                units.push(c as u16);
+                i += len;
+            }
+            None => {
+                units.push(0xFFFD);
+                i += 1;
+            }
+        }
+    }
+    units
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_path() {
+        let path = path_from_bytes(b"foo/b\xffr.txt".to_vec());
+        let json = serde_json::to_string(&JsonPath::from_path(&path)).unwrap();
+        assert_eq!(json, r#"{"base64":"Zm9vL2L/ci50eHQ="}"#);
+        let decoded: JsonPath = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.into_path().unwrap(), path);
+
+        let json = serde_json::to_string(&JsonPath::from_path(Path::new("foo/bar"))).unwrap();
+        assert_eq!(json, r#""foo/bar""#);
+    }
+
+    #[test]
+    fn test_wtf8() {
+        // Unpaired surrogate, surrogate pair of U+1F600 and two-byte code point
+        let units = [0x66, 0xD800, 0x6F, 0xD83D, 0xDE00, 0xE9];
+        let bytes = wide_to_wtf8(units.iter().copied());
+        assert_eq!(bytes, b"f\xed\xa0\x80o\xf0\x9f\x98\x80\xc3\xa9");
+        assert_eq!(wtf8_to_wide(&bytes), units);
+        assert_eq!(wtf8_to_wide(b"a\xffb"), [0x61, 0xFFFD, 0x62]);
+    }
+}
@@ -0,0 +1,181 @@
+//! Progress of comparisons
+//!
+//! [Progress] is an [Observer] of comparisons that keeps the numbers of the current phase, which
+//! can be read from another thread while a comparison runs.
+
+use std::ffi::OsStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::workers::current_worker;
+use crate::{ConfirmerError, Observer};
+
+/// Phase of a comparison
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Phase {
+    /// No comparison is running
+    #[default]
+    Idle,
+    /// Walking source, and destinations unless they are walked while hashed, the totals grow as
+    /// files are found
+    Walk,
+    /// Hashing beginnings and ends of large files to rule out files of the same size
+    FileEnds,
+    /// Hashing files in source
+    Source,
+    /// Hashing files in destinations, the totals grow as destinations are walked unless all
+    /// copies or extra files are requested
+    Destinations,
+}
+
+/// Progress of the current phase at a point in time
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProgressSnapshot {
+    /// Current phase
+    pub phase: Phase,
+    /// Number of files processed in the phase
+    pub files_done: u64,
+    /// Number of files to process in the phase, equal to `files_done` while walking
+    pub files_total: u64,
+    /// Number of bytes hashed in the phase, or total size of files found while walking
+    pub bytes_done: u64,
+    /// Number of bytes to hash in the phase, equal to `bytes_done` while walking
+    pub bytes_total: u64,
+    /// Number of bytes hashed in the phase by each worker thread, their sum is `bytes_done`
+    /// outside of the walk
+    pub worker_bytes: Vec<u64>,
+    /// Time since the start of the phase
+    pub elapsed: Duration,
+}
+
+impl ProgressSnapshot {
+    /// Average number of bytes processed per second in the phase
+    pub fn bytes_per_sec(&self) -> f64 {
+        match self.elapsed.as_secs_f64() {
+            secs if secs > 0.0 => self.bytes_done as f64 / secs,
+            _ => 0.0,
+        }
+    }
+
+    /// Estimated time left to the end of the phase at the average throughput so far
+    ///
+    /// Returns None until some bytes are processed.
+    pub fn eta(&self) -> Option<Duration> {
+        let rate = self.bytes_per_sec();
+        if rate <= 0.0 {
+            return None;
+        }
+        let bytes_left = self.bytes_total.saturating_sub(self.bytes_done);
+        Some(Duration::from_secs_f64(bytes_left as f64 / rate))
+    }
+}
+
+/// Shared progress of the comparisons of a [CopyConfirmer](crate::CopyConfirmer)
+///
+/// Get it with [progress](crate::CopyConfirmer::progress). Clones share the same numbers, so a
+/// clone can be polled from another thread while the comparison runs.
+#[derive(Clone, Debug)]
+pub struct Progress {
+    inner: Arc<ProgressState>,
+}
+
+#[derive(Debug)]
+struct ProgressState {
+    /// Current phase with the time it started
+    phase: Mutex<(Phase, Instant)>,
+    /// Files found count only in [Phase::Walk], not while destinations are walked and hashed
+    walking: AtomicBool,
+    files_done: AtomicU64,
+    files_total: AtomicU64,
+    bytes_done: AtomicU64,
+    bytes_total: AtomicU64,
+    /// Bytes hashed by each worker, indexed by [current_worker]
+    worker_bytes: Vec<AtomicU64>,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self::with_workers(0)
+    }
+}
+
+impl Progress {
+    /// Create progress of comparisons hashing files in `num_workers` worker threads
+    pub(crate) fn with_workers(num_workers: usize) -> Self {
+        let inner = ProgressState {
+            phase: Mutex::new((Phase::Idle, Instant::now())),
+            walking: AtomicBool::new(false),
+            files_done: AtomicU64::new(0),
+            files_total: AtomicU64::new(0),
+            bytes_done: AtomicU64::new(0),
+            bytes_total: AtomicU64::new(0),
+            worker_bytes: (0..num_workers).map(|_| AtomicU64::new(0)).collect(),
+        };
+        Self { inner: Arc::new(inner) }
+    }
+
+    /// Get the current progress
+    pub fn snapshot(&self) -> ProgressSnapshot {
+        let (phase, started) = *self.inner.phase.lock().expect("Progress lock is poisoned");
+        ProgressSnapshot {
+            phase,
+            files_done: self.inner.files_done.load(Ordering::Relaxed),
+            files_total: self.inner.files_total.load(Ordering::Relaxed),
+            bytes_done: self.inner.bytes_done.load(Ordering::Relaxed),
+            bytes_total: self.inner.bytes_total.load(Ordering::Relaxed),
+            worker_bytes: self
+                .inner
+                .worker_bytes
+                .iter()
+                .map(|bytes| bytes.load(Ordering::Relaxed))
+                .collect(),
+            elapsed: started.elapsed(),
+        }
+    }
+}
+
+impl Observer for Progress {
+    /// Start a phase and reset the numbers, the numbers of the last phase are kept once the
+    /// comparison ends
+    fn phase_changed(&self, phase: Phase, files_total: u64, bytes_total: u64) {
+        let mut current = self.inner.phase.lock().expect("Progress lock is poisoned");
+        if phase != Phase::Idle {
+            self.inner.files_done.store(0, Ordering::Relaxed);
+            self.inner.files_total.store(files_total, Ordering::Relaxed);
+            self.inner.bytes_done.store(0, Ordering::Relaxed);
+            self.inner.bytes_total.store(bytes_total, Ordering::Relaxed);
+            self.inner.worker_bytes.iter().for_each(|bytes| bytes.store(0, Ordering::Relaxed));
+        }
+        self.inner.walking.store(phase == Phase::Walk, Ordering::Relaxed);
+        *current = (phase, Instant::now());
+    }
+
+    fn file_discovered(&self, _path: &OsStr, size: Option<u64>) {
+        if !self.inner.walking.load(Ordering::Relaxed) {
+            return;
+        }
+        let size = size.unwrap_or(0);
+        self.inner.files_done.fetch_add(1, Ordering::Relaxed);
+        self.inner.files_total.fetch_add(1, Ordering::Relaxed);
+        self.inner.bytes_done.fetch_add(size, Ordering::Relaxed);
+        self.inner.bytes_total.fetch_add(size, Ordering::Relaxed);
+    }
+
+    fn hash_queued(&self, _path: &OsStr, bytes: u64) {
+        self.inner.files_total.fetch_add(1, Ordering::Relaxed);
+        self.inner.bytes_total.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn bytes_hashed(&self, bytes: u64) {
+        self.inner.bytes_done.fetch_add(bytes, Ordering::Relaxed);
+        let worker = current_worker().and_then(|index| self.inner.worker_bytes.get(index));
+        if let Some(worker) = worker {
+            worker.fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+
+    fn hash_finished(&self, _path: &OsStr, _hash: Result<&str, &ConfirmerError>) {
+        self.inner.files_done.fetch_add(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,236 @@
+//! Walking directories and listing manifests
+//!
+//! Each location is walked once, the files found are used both for comparison and for the
+//! statistics. Locations can be walked in parallel, which helps on network mounts and slow disks.
+//! Files can also be handled one by one as they are found, without listing the whole location.
+
+use std::ffi::{OsStr, OsString};
+use std::path::Path;
+use std::thread;
+
+use log::debug;
+use walkdir::WalkDir;
+
+use crate::exclude::{ExcludeMatcher, IgnoreStack};
+use crate::{
+    skip_or_abort, ConfirmerError, ErrorPolicy, FileEntry, HashAlgorithm, Location, Observer,
+    SymlinkPolicy, UnreadableFile,
+};
+
+/// Links that could not be followed, see [SymlinkPolicy]
+#[derive(Default)]
+pub(crate) struct BrokenLinks {
+    pub dangling: Vec<OsString>,
+    pub loops: Vec<OsString>,
+}
+
+/// Settings of a walk of a single location
+pub(crate) struct WalkSettings<'a> {
+    pub error_policy: ErrorPolicy,
+    pub symlink_policy: SymlinkPolicy,
+    /// Algorithm of checksums of symbolic links compared by their targets
+    pub hash_algorithm: HashAlgorithm,
+    /// Skip paths ignored by ignore files, see [IGNORE_FILE_NAMES](crate::IGNORE_FILE_NAMES)
+    pub ignore_files: bool,
+    /// Files and directories matching it are skipped without being reported
+    pub pruned: Option<&'a ExcludeMatcher<'a>>,
+    /// Observer notified of the files found and of the errors
+    pub observer: &'a dyn Observer,
+}
+
+/// Files found in a single location together with the paths left out
+#[derive(Default)]
+pub(crate) struct Listing {
+    pub files: Vec<FileEntry>,
+    /// Paths ignored by ignore files
+    pub ignored: Vec<OsString>,
+    pub broken_links: BrokenLinks,
+    pub unreadable: Vec<UnreadableFile>,
+}
+
+impl Listing {
+    /// Move the paths left out to the vectors of the comparison and return the files
+    ///
+    /// # Arguments
+    /// * `excluded_files` - vector to add the ignored paths to
+    /// * `broken_links` - links that could not be followed
+    /// * `unreadable_files` - vector to add the files skipped because of an error to
+    pub(crate) fn into_files(
+        mut self,
+        excluded_files: &mut Vec<OsString>,
+        broken_links: &mut BrokenLinks,
+        unreadable_files: &mut Vec<UnreadableFile>,
+    ) -> Vec<FileEntry> {
+        excluded_files.append(&mut self.ignored);
+        broken_links.dangling.append(&mut self.broken_links.dangling);
+        broken_links.loops.append(&mut self.broken_links.loops);
+        unreadable_files.append(&mut self.unreadable);
+        self.files
+    }
+}
+
+/// Collect files in all locations, each in its own thread if `parallel` is set
+///
+/// Returns the listings in the order of `locations`, or the error of the first location that
+/// failed.
+pub(crate) fn list_locations(
+    locations: &[(&Location, WalkSettings)],
+    parallel: bool,
+) -> Result<Vec<Listing>, ConfirmerError> {
+    if !parallel {
+        return locations
+            .iter()
+            .map(|(location, settings)| list_files(location, settings))
+            .collect();
+    }
+    thread::scope(|scope| {
+        let handles: Vec<_> = locations
+            .iter()
+            .map(|(location, settings)| scope.spawn(move || list_files(location, settings)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().map_err(|_| ConfirmerError::ThreadPanic)?)
+            .collect()
+    })
+}
+
+/// Collect all files in directory or manifest, see [walk_files]
+pub(crate) fn list_files(
+    location: &Location,
+    settings: &WalkSettings,
+) -> Result<Listing, ConfirmerError> {
+    let mut files = vec![];
+    let mut listing = visit_files(location, settings, &mut |file| {
+        files.push(file);
+        true
+    })?;
+    listing.files = files;
+    Ok(listing)
+}
+
+/// Pass each file in directory or manifest to `on_file` as it is found, see [walk_files]
+///
+/// The walk stops once `on_file` returns false. The returned listing holds the paths left out,
+/// but no files.
+pub(crate) fn visit_files(
+    location: &Location,
+    settings: &WalkSettings,
+    on_file: &mut dyn FnMut(FileEntry) -> bool,
+) -> Result<Listing, ConfirmerError> {
+    match location {
+        Location::Dir(dir) => walk_files(dir, settings, on_file),
+        Location::Manifest(manifest) => {
+            for entry in &manifest.entries {
+                let file = FileEntry::from_manifest_entry(&manifest.root, entry);
+                if settings.pruned.map_or(false, |m| m.is_excluded(&file.path, &file.relative_path))
+                {
+                    continue;
+                }
+                settings.observer.file_discovered(&file.path, file.size);
+                if !on_file(file) {
+                    break;
+                }
+            }
+            Ok(Listing::default())
+        }
+    }
+}
+
+/// Go recursively through directory and pass all files with their sizes to `on_file`
+///
+/// Returns ConfirmerError::Walk if any path cannot be accessed, unless errors are skipped by
+/// the error policy. Error on the directory itself is always returned. Symbolic links are
+/// handled according to the symlink policy.
+///
+/// # Arguments
+/// * `dir` - directory to go through
+/// * `settings` - settings of the walk
+/// * `on_file` - called with each file found, returns whether to go on with the walk
+fn walk_files(
+    dir: &OsStr,
+    settings: &WalkSettings,
+    on_file: &mut dyn FnMut(FileEntry) -> bool,
+) -> Result<Listing, ConfirmerError> {
+    let follow_links = settings.symlink_policy == SymlinkPolicy::Follow;
+    let mut listing = Listing::default();
+    let mut ignore_stack = IgnoreStack::new();
+    let mut walker = WalkDir::new(dir).follow_links(follow_links).into_iter();
+    while let Some(item) = walker.next() {
+        let item = match item {
+            Ok(item) => item,
+            Err(e) if e.depth() == 0 => return Err(e.into()),
+            Err(e) => {
+                let path = e.path().map_or_else(OsString::new, |p| p.as_os_str().to_owned());
+                if e.loop_ancestor().is_some() {
+                    debug!("Skipping symlink loop {:?}", path);
+                    listing.broken_links.loops.push(path);
+                } else if follow_links && is_dangling_link(Path::new(&path)) {
+                    debug!("Skipping dangling link {:?}", path);
+                    listing.broken_links.dangling.push(path);
+                } else {
+                    let error = e.into();
+                    settings.observer.error(&path, &error);
+                    skip_or_abort(settings.error_policy, path, error, &mut listing.unreadable)?;
+                }
+                continue;
+            }
+        };
+        if let Some(matcher) = settings.pruned {
+            let relative_path = item.path().strip_prefix(dir).unwrap_or(item.path());
+            if item.depth() > 0 && matcher.is_excluded(item.path().as_os_str(), relative_path) {
+                debug!("Skipping excluded path {:?}", item.path());
+                if item.file_type().is_dir() {
+                    walker.skip_current_dir();
+                }
+                continue;
+            }
+        }
+        if settings.ignore_files {
+            if ignore_stack.is_ignored(&item) {
+                if item.file_type().is_dir() {
+                    walker.skip_current_dir();
+                }
+                listing.ignored.push(item.path().as_os_str().to_owned());
+                continue;
+            }
+            if item.file_type().is_dir() {
+                ignore_stack.push_dir(&item)?;
+            }
+        }
+
+        let path = item.path().as_os_str().to_owned();
+        let file = if item.file_type().is_symlink() {
+            if settings.symlink_policy != SymlinkPolicy::CompareTarget {
+                continue;
+            }
+            if is_dangling_link(item.path()) {
+                listing.broken_links.dangling.push(path.clone());
+            }
+            FileEntry::from_symlink(item, Path::new(dir), settings.hash_algorithm)
+        } else if item.file_type().is_file() {
+            FileEntry::from_dir_entry(item, Path::new(dir))
+        } else {
+            continue;
+        };
+        match file {
+            Ok(file) => {
+                settings.observer.file_discovered(&file.path, file.size);
+                if !on_file(file) {
+                    break;
+                }
+            }
+            Err(e) => {
+                settings.observer.error(&path, &e);
+                skip_or_abort(settings.error_policy, path, e, &mut listing.unreadable)?;
+            }
+        }
+    }
+    Ok(listing)
+}
+
+/// Returns true if path is a symbolic link pointing to a nonexistent file
+fn is_dangling_link(path: &Path) -> bool {
+    let is_link = std::fs::symlink_metadata(path).map_or(false, |m| m.file_type().is_symlink());
+    is_link && std::fs::metadata(path).is_err()
+}
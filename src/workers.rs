@@ -0,0 +1,81 @@
+//! Threads calculating checksums
+//!
+//! Jobs wait in a bounded queue, so that whoever adds them has to wait for the workers instead of
+//! piling up jobs and results faster than they are read.
+
+use std::cell::Cell;
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Number of jobs waiting in the queue per worker
+const QUEUED_JOBS_PER_WORKER: usize = 4;
+
+/// Job run by a worker
+pub(crate) type Job = Box<dyn FnOnce() + Send>;
+
+thread_local! {
+    /// Index of the worker running on the current thread
+    static WORKER_INDEX: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Get index of the worker running on the current thread, none outside of the workers
+pub(crate) fn current_worker() -> Option<usize> {
+    WORKER_INDEX.with(Cell::get)
+}
+
+/// Fixed number of threads running jobs from a bounded queue
+///
+/// The threads stop once the workers are dropped and the queue is empty.
+pub(crate) struct Workers {
+    jobs: SyncSender<Job>,
+    capacity: usize,
+}
+
+impl Workers {
+    /// Start the worker threads
+    ///
+    /// # Arguments
+    /// * `num_threads` - number of jobs run in parallel, must be at least one
+    pub(crate) fn new(num_threads: usize) -> Self {
+        assert!(num_threads > 0, "At least one worker thread is needed");
+        let (jobs, queue) = sync_channel::<Job>(num_threads * QUEUED_JOBS_PER_WORKER);
+        let queue = Arc::new(Mutex::new(queue));
+        for index in 0..num_threads {
+            let queue = Arc::clone(&queue);
+            thread::spawn(move || {
+                WORKER_INDEX.with(|worker| worker.set(Some(index)));
+                loop {
+                    // The lock is released before the job runs
+                    let job = queue.lock().expect("Job queue lock is poisoned").recv();
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => break,
+                    }
+                }
+            });
+        }
+        Self { jobs, capacity: num_threads * (QUEUED_JOBS_PER_WORKER + 1) }
+    }
+
+    /// Number of jobs that can be queued or running at a time
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Add job to the queue, waiting until there is room for it
+    pub(crate) fn execute(&self, job: Job) {
+        self.jobs.send(job).expect("Worker threads have stopped");
+    }
+
+    /// Add job to the queue if there is room for it
+    ///
+    /// Returns the job back if the queue is full
+    pub(crate) fn try_execute(&self, job: Job) -> Result<(), Job> {
+        match self.jobs.try_send(job) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(job)) => Err(job),
+            Err(TrySendError::Disconnected(_)) => panic!("Worker threads have stopped"),
+        }
+    }
+}